@@ -1,5 +1,102 @@
+use async_service::*;
+use atlas::*;
 use bevy::prelude::*;
+use bevy::tasks::IoTaskPool;
+
+/// Size of a grid cell in world units, the tiles are drawn at their pixel size
+const CELL: f32 = 32.0;
 
 fn main() -> AppExit {
-    App::new().add_plugins(DefaultPlugins).run()
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(ImagePlugin::default_nearest()),
+            AtlasPlugin,
+            AsyncServicePlugin,
+        ))
+        .add_systems(
+            Startup,
+            (spawn_camera, spawn_floor, spawn_belts, spawn_assembler),
+        )
+        .run()
+}
+
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn((Camera2d, Transform::from_xyz(4.5 * CELL, 3.5 * CELL, 0.0)));
+}
+
+/// Center of the tile at `cell`, on layer `z`
+fn cell_position(cell: IVec2, z: f32) -> Transform {
+    Transform::from_translation((cell.as_vec2() * CELL).extend(z))
+}
+
+/// Ground surrounded by walls
+fn spawn_floor(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let ground: Handle<AtlasEntry> = asset_server.load("tiles/tiles.meta.yml#ground");
+    let wall: Handle<AtlasEntry> = asset_server.load("tiles/tiles.meta.yml#wall");
+    for y in 0..8 {
+        for x in 0..10 {
+            let tile = match x == 0 || y == 0 || x == 9 || y == 7 {
+                true => wall.clone(),
+                false => ground.clone(),
+            };
+            commands.spawn((AtlasSprite::new(tile), cell_position(IVec2::new(x, y), 0.0)));
+        }
+    }
+}
+
+/// A belt loop around the assembler, all belts move in lockstep
+fn spawn_belts(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let belt: Handle<AtlasEntry> = asset_server.load("tile_entities/belts.meta.yml#belt");
+    // start, direction, length and quarter turns of each side, the belt tiles face east
+    let sides = [
+        (IVec2::new(2, 2), IVec2::X, 5, 0),
+        (IVec2::new(7, 2), IVec2::Y, 3, 1),
+        (IVec2::new(7, 5), IVec2::NEG_X, 5, 2),
+        (IVec2::new(2, 5), IVec2::NEG_Y, 3, 3),
+    ];
+    for (start, step, length, rotation) in sides {
+        for i in 0..length {
+            let mut sprite = AtlasSprite::new(belt.clone());
+            sprite.state.rotation = rotation;
+            commands.spawn((
+                sprite,
+                AtlasAnimator::synced("belts"),
+                cell_position(start + step * i, 1.0),
+            ));
+        }
+    }
+}
+
+/// Places the assembler once its definition is known, as its footprint decides where it is centered
+fn spawn_assembler(asset_server: Res<AssetServer>, async_service: Res<AsyncService>) {
+    let asset_server = asset_server.clone();
+    let async_service = async_service.clone();
+    IoTaskPool::get()
+        .spawn(async move {
+            let assembler: Handle<AtlasEntry> =
+                asset_server.load("tile_entities/assembler.meta.yml#assembler");
+            if let Err(error) = asset_server.wait_for_asset(&assembler).await {
+                warn!("not placing the assembler: {error}");
+                return;
+            }
+            async_service
+                .exec_sync(place_assembler, (assembler, IVec2::new(4, 3)))
+                .await;
+        })
+        .detach();
+}
+
+/// Spawns the assembler with the bottom left cell of its footprint at `cell`
+fn place_assembler(
+    In((assembler, cell)): In<(Handle<AtlasEntry>, IVec2)>,
+    entries: Res<Assets<AtlasEntry>>,
+    mut commands: Commands,
+) {
+    let Some(entry) = entries.get(&assembler) else {
+        return;
+    };
+    let center = (entry.definition.footprint(0).as_vec2() - 1.0) * 0.5 * CELL;
+    let mut transform = cell_position(cell, 2.0);
+    transform.translation += center.extend(0.0);
+    commands.spawn((AtlasSprite::new(assembler), transform));
 }
@@ -0,0 +1,52 @@
+use async_service::*;
+use bevy::prelude::*;
+use grayscale::*;
+
+#[path = "shared/grayscale.rs"]
+mod grayscale;
+
+/// Same as the image_converter example, but the grayscale image is declared as a derived asset.
+/// Run with `--features bevy/file_watcher` and edit `assets/image.png` to see it update.
+fn main() -> AppExit {
+    App::new()
+        .add_plugins(DefaultPlugins)
+        .add_plugins(AsyncServicePlugin)
+        .add_derived_asset(clone_image, |image| image_to_grayscale_converter(&image))
+        .add_systems(Startup, (setup, spawn_camera))
+        .run()
+}
+
+fn spawn_camera(mut commands: Commands) {
+    commands.spawn(Camera2d);
+}
+
+fn setup(
+    asset_server: Res<AssetServer>,
+    mut derive: DeriveAssets<Image, Image>,
+    mut commands: Commands,
+) {
+    let handle_color: Handle<Image> = asset_server.load("image.png");
+    let handle_gray = derive.derive(handle_color.clone());
+
+    commands
+        .spawn(Node {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            column_gap: Val::Px(16.0),
+            ..default()
+        })
+        .with_children(|root| {
+            [handle_color, handle_gray].into_iter().for_each(|image| {
+                root.spawn((
+                    ImageNode::new(image),
+                    Node {
+                        width: Val::Px(512.0),
+                        height: Val::Px(512.0),
+                        ..default()
+                    },
+                ));
+            })
+        });
+}
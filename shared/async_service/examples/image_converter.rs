@@ -2,7 +2,10 @@ use std::convert::Infallible;
 
 use async_service::*;
 use bevy::prelude::*;
-use bevy::render::render_resource::TextureFormat;
+use grayscale::*;
+
+#[path = "shared/grayscale.rs"]
+mod grayscale;
 
 fn main() -> AppExit {
     App::new()
@@ -26,9 +29,12 @@ fn setup(asset_server: Res<AssetServer>, async_service: Res<AsyncService>, mut c
         async move {
             asset_server.wait_for_asset(&handle_color).await.unwrap();
 
-            // Automaticaly runs this system on the next update and then continue
+            // Copies the image out of the world on the next update,
+            // then converts it on the async compute pool instead of the main thread
             let image_gray = async_service
-                .exec_sync(image_to_grayscale, handle_color)
+                .exec_compute(clone_image, handle_color, |image| {
+                    image_to_grayscale_converter(&image)
+                })
                 .await;
 
             Ok(image_gray)
@@ -38,41 +44,6 @@ fn setup(asset_server: Res<AssetServer>, async_service: Res<AsyncService>, mut c
     spawn_ui(&mut commands, handle_color, handle_gray);
 }
 
-fn image_to_grayscale(In(h): In<Handle<Image>>, images: Res<Assets<Image>>) -> Image {
-    let image = images.get(&h).expect("requested image not loaded");
-    image_to_grayscale_converter(image)
-}
-
-/// helper function to convert an image to grayscale by modifying its pixel data on the CPU
-fn image_to_grayscale_converter(image: &Image) -> Image {
-    // Convert to a predictable 4-byte-per-pixel format first.
-    let mut img = image
-        .clone()
-        .convert(TextureFormat::Rgba8UnormSrgb)
-        .expect("failed to convert image to RGBA8");
-
-    let data = img.data.as_mut().expect("image has no CPU-side pixel data");
-
-    // RGBA8 pixel layout: [r, g, b, a, r, g, b, a, ...]
-    for px in data.chunks_exact_mut(4) {
-        let r = px[0] as f32;
-        let g = px[1] as f32;
-        let b = px[2] as f32;
-
-        // Perceptual luma (BT.709-ish)
-        let gray = (0.2126 * r + 0.7152 * g + 0.0722 * b)
-            .round()
-            .clamp(0.0, 255.0) as u8;
-
-        px[0] = gray;
-        px[1] = gray;
-        px[2] = gray;
-        // px[3] (alpha) preserved
-    }
-
-    img
-}
-
 fn spawn_ui(commands: &mut Commands, handle_color: Handle<Image>, handle_gray: Handle<Image>) {
     commands
         .spawn(Node {
@@ -0,0 +1,38 @@
+//! Helpers shared by the image examples
+
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureFormat;
+
+pub fn clone_image(In(h): In<Handle<Image>>, images: Res<Assets<Image>>) -> Image {
+    images.get(&h).expect("requested image not loaded").clone()
+}
+
+/// helper function to convert an image to grayscale by modifying its pixel data on the CPU
+pub fn image_to_grayscale_converter(image: &Image) -> Image {
+    // Convert to a predictable 4-byte-per-pixel format first.
+    let mut img = image
+        .clone()
+        .convert(TextureFormat::Rgba8UnormSrgb)
+        .expect("failed to convert image to RGBA8");
+
+    let data = img.data.as_mut().expect("image has no CPU-side pixel data");
+
+    // RGBA8 pixel layout: [r, g, b, a, r, g, b, a, ...]
+    for px in data.chunks_exact_mut(4) {
+        let r = px[0] as f32;
+        let g = px[1] as f32;
+        let b = px[2] as f32;
+
+        // Perceptual luma (BT.709-ish)
+        let gray = (0.2126 * r + 0.7152 * g + 0.0722 * b)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+
+        px[0] = gray;
+        px[1] = gray;
+        px[2] = gray;
+        // px[3] (alpha) preserved
+    }
+
+    img
+}
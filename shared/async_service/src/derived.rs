@@ -0,0 +1,160 @@
+//! Assets that are computed from another asset and the world, see [`DerivedAssetAppExt`]
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use bevy::asset::StrongHandle;
+use bevy::ecs::system::SystemParam;
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+
+use crate::AsyncService;
+
+pub trait DerivedAssetAppExt {
+    /// Declares `B = compute(extract(A, world))`.
+    /// Takes the same arguments as [`AsyncService::exec_compute`](crate::AsyncService::exec_compute),
+    /// which is rerun through the bridge every time a source asset finished (re)loading or got modified.
+    /// Requires the [`AsyncServicePlugin`](crate::AsyncServicePlugin).
+    /// Derived handles are created with [`DeriveAssets::derive`].
+    fn add_derived_asset<A: Asset, B: Asset, D, M>(
+        &mut self,
+        extract: impl IntoSystem<In<Handle<A>>, D, M> + Copy + Send + Sync + 'static,
+        compute: impl FnOnce(D) -> B + Copy + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        D: Send + Sync + 'static;
+}
+
+impl DerivedAssetAppExt for App {
+    fn add_derived_asset<A: Asset, B: Asset, D, M>(
+        &mut self,
+        extract: impl IntoSystem<In<Handle<A>>, D, M> + Copy + Send + Sync + 'static,
+        compute: impl FnOnce(D) -> B + Copy + Send + Sync + 'static,
+    ) -> &mut Self
+    where
+        D: Send + Sync + 'static,
+    {
+        assert!(
+            !self.world().contains_resource::<DerivedAssets<A, B>>(),
+            "a derived asset from {} to {} is already registered",
+            A::type_path(),
+            B::type_path()
+        );
+        let derive: DeriveFn<A, B> = Arc::new(move |service, source| {
+            let service = service.clone();
+            Box::pin(async move { service.exec_compute(extract, source, compute).await })
+        });
+        self.insert_resource(DerivedAssets::<A, B> {
+            derive,
+            entries: Vec::new(),
+        })
+        .add_systems(Update, update_derived_assets::<A, B>)
+    }
+}
+
+/// Creates handles to assets derived with the functions registered in [`DerivedAssetAppExt::add_derived_asset`]
+#[derive(SystemParam)]
+pub struct DeriveAssets<'w, A: Asset, B: Asset> {
+    derived: ResMut<'w, DerivedAssets<A, B>>,
+    targets: Res<'w, Assets<B>>,
+}
+
+impl<A: Asset, B: Asset> DeriveAssets<'_, A, B> {
+    /// Returns a handle that is filled as soon as `source` is loaded and updated whenever it changes.
+    /// Dropping all clones of the returned handle stops the updates.
+    pub fn derive(&mut self, source: Handle<A>) -> Handle<B> {
+        let target = self.targets.reserve_handle();
+        let Handle::Strong(strong) = &target else {
+            unreachable!("reserved handles are strong");
+        };
+        self.derived.entries.push(DerivedEntry {
+            source,
+            target: target.id(),
+            target_alive: Arc::downgrade(strong),
+            fresh: true,
+            generation: 0,
+        });
+        target
+    }
+}
+
+/// Extracts and computes one derived asset through the bridge
+type DeriveFn<A, B> =
+    Arc<dyn Fn(&AsyncService, Handle<A>) -> Pin<Box<dyn Future<Output = B> + Send>> + Send + Sync>;
+
+#[derive(Resource)]
+struct DerivedAssets<A: Asset, B: Asset> {
+    derive: DeriveFn<A, B>,
+    entries: Vec<DerivedEntry<A, B>>,
+}
+
+struct DerivedEntry<A: Asset, B: Asset> {
+    /// keeps the source alive, so it can still be derived from after a reload
+    source: Handle<A>,
+    target: AssetId<B>,
+    /// dead once every handle to the target got dropped, even if nothing was inserted yet
+    target_alive: Weak<StrongHandle>,
+    /// not derived yet, the source may have finished loading before the entry was created
+    fresh: bool,
+    /// counts the derivations started, results of outdated ones are discarded
+    generation: u32,
+}
+
+fn update_derived_assets<A: Asset, B: Asset>(
+    mut derived: ResMut<DerivedAssets<A, B>>,
+    mut source_events: MessageReader<AssetEvent<A>>,
+    sources: Res<Assets<A>>,
+    service: Res<AsyncService>,
+) {
+    let derived = &mut *derived;
+    derived
+        .entries
+        .retain(|entry| entry.target_alive.strong_count() > 0);
+
+    let changed = source_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for entry in derived.entries.iter_mut() {
+        let loaded = sources.contains(&entry.source);
+        let dirty = changed.contains(&entry.source.id()) || (entry.fresh && loaded);
+        if !dirty {
+            continue;
+        }
+        entry.fresh = false;
+        entry.generation += 1;
+
+        let asset = (derived.derive)(&service, entry.source.clone());
+        let (target, generation) = (entry.target, entry.generation);
+        let service = service.clone();
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let asset = asset.await;
+                service
+                    .exec_sync(insert_derived::<A, B>, (target, generation, asset))
+                    .await;
+            })
+            .detach();
+    }
+}
+
+fn insert_derived<A: Asset, B: Asset>(
+    In((target, generation, asset)): In<(AssetId<B>, u32, B)>,
+    derived: Res<DerivedAssets<A, B>>,
+    mut targets: ResMut<Assets<B>>,
+) {
+    let current = derived.entries.iter().any(|entry| {
+        entry.target == target
+            && entry.generation == generation
+            && entry.target_alive.strong_count() > 0
+    });
+    if current {
+        targets.insert(target, asset).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Diagnostics for the async bridge, see [`AsyncServiceDiagnosticsPlugin`]
+
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bevy::diagnostic::Diagnostic;
+use bevy::diagnostic::DiagnosticMeasurement;
+use bevy::diagnostic::DiagnosticPath;
+use bevy::diagnostic::DiagnosticsStore;
+use bevy::diagnostic::RegisterDiagnostic;
+use bevy::platform::collections::HashMap;
+use bevy::platform::time::Instant;
+use bevy::prelude::*;
+
+use crate::AsyncService;
+
+/// Records the state of the async bridge into the [`DiagnosticsStore`].
+/// Every system called through the bridge is its own bridge, with the diagnostics
+/// [`Self::bridge_queued`] and [`Self::bridge_executed`] registered once it got its first job.
+pub struct AsyncServiceDiagnosticsPlugin;
+
+impl AsyncServiceDiagnosticsPlugin {
+    /// Jobs left in the queue after the last update, including batched inputs that were not run yet
+    pub const QUEUED: DiagnosticPath = DiagnosticPath::const_new("async_service/queued");
+    /// Jobs executed in the last update
+    pub const EXECUTED: DiagnosticPath = DiagnosticPath::const_new("async_service/executed");
+    /// Inputs of `exec_sync_batched` that were run in the last update, over all batch systems
+    pub const BATCHED: DiagnosticPath = DiagnosticPath::const_new("async_service/batched");
+    /// Bridges whose system still has jobs waiting after the last update
+    pub const PROCESSORS: DiagnosticPath = DiagnosticPath::const_new("async_service/processors");
+    /// Mean time between calling `exec_sync` and receiving the response, in milliseconds
+    pub const LATENCY: DiagnosticPath = DiagnosticPath::const_new("async_service/latency");
+
+    /// Jobs of the bridge of `system` left in the queue after the last update
+    pub fn bridge_queued<S>(_system: &S) -> DiagnosticPath {
+        Self::bridge_path(std::any::type_name::<S>(), "queued")
+    }
+
+    /// Jobs of the bridge of `system` executed in the last update
+    pub fn bridge_executed<S>(_system: &S) -> DiagnosticPath {
+        Self::bridge_path(std::any::type_name::<S>(), "executed")
+    }
+
+    fn bridge_path(bridge: &str, stat: &str) -> DiagnosticPath {
+        let bridge = ShortName(bridge).to_string();
+        DiagnosticPath::from_components(["async_service", "bridge", &bridge, stat])
+    }
+}
+
+impl Plugin for AsyncServiceDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::QUEUED))
+            .register_diagnostic(Diagnostic::new(Self::EXECUTED))
+            .register_diagnostic(Diagnostic::new(Self::BATCHED))
+            .register_diagnostic(Diagnostic::new(Self::PROCESSORS))
+            .register_diagnostic(Diagnostic::new(Self::LATENCY).with_suffix("ms"))
+            .add_systems(Last, record_diagnostics);
+    }
+}
+
+/// Bookkeeping of the job queue, always collected since it is cheap
+#[derive(Resource, Default)]
+pub(crate) struct BridgeStats {
+    pub(crate) queued: usize,
+    pub(crate) executed: usize,
+    pub(crate) batched: usize,
+    pub(crate) processors: usize,
+    /// keyed by the type name of the system
+    pub(crate) bridges: HashMap<&'static str, BridgeCounts>,
+}
+
+#[derive(Default)]
+pub(crate) struct BridgeCounts {
+    pub(crate) queued: usize,
+    pub(crate) executed: usize,
+}
+
+/// Jobs per bridge that were sent but not executed yet, counted from both sides of the queue
+#[derive(Default)]
+pub(crate) struct QueuedJobs(Mutex<HashMap<&'static str, usize>>);
+
+impl QueuedJobs {
+    pub(crate) fn push(&self, bridge: &'static str) {
+        *self.0.lock().unwrap().entry(bridge).or_default() += 1;
+    }
+
+    pub(crate) fn pop(&self, bridge: &'static str) {
+        if let Some(queued) = self.0.lock().unwrap().get_mut(bridge) {
+            *queued -= 1;
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> Vec<(&'static str, usize)> {
+        let queued = self.0.lock().unwrap();
+        queued
+            .iter()
+            .map(|(bridge, queued)| (*bridge, *queued))
+            .collect()
+    }
+}
+
+/// Filled from the async side, since only it knows when a response arrived
+#[derive(Default)]
+pub(crate) struct LatencyStats {
+    total_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl LatencyStats {
+    pub(crate) fn record(&self, latency: Duration) {
+        self.total_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Mean latency since the last call, `None` if nothing was recorded
+    fn take_mean(&self) -> Option<Duration> {
+        let count = self.count.swap(0, Ordering::Relaxed);
+        let total = self.total_micros.swap(0, Ordering::Relaxed);
+        (count > 0).then(|| Duration::from_micros(total / count))
+    }
+}
+
+fn record_diagnostics(
+    mut store: ResMut<DiagnosticsStore>,
+    stats: Res<BridgeStats>,
+    service: Res<AsyncService>,
+) {
+    measure(
+        &mut store,
+        &AsyncServiceDiagnosticsPlugin::QUEUED,
+        stats.queued as f64,
+    );
+    measure(
+        &mut store,
+        &AsyncServiceDiagnosticsPlugin::EXECUTED,
+        stats.executed as f64,
+    );
+    measure(
+        &mut store,
+        &AsyncServiceDiagnosticsPlugin::BATCHED,
+        stats.batched as f64,
+    );
+    measure(
+        &mut store,
+        &AsyncServiceDiagnosticsPlugin::PROCESSORS,
+        stats.processors as f64,
+    );
+    for (bridge, counts) in &stats.bridges {
+        for (stat, value) in [("queued", counts.queued), ("executed", counts.executed)] {
+            let path = AsyncServiceDiagnosticsPlugin::bridge_path(bridge, stat);
+            if store.get(&path).is_none() {
+                store.add(Diagnostic::new(path.clone()));
+            }
+            measure(&mut store, &path, value as f64);
+        }
+    }
+    if let Some(latency) = service.latency.take_mean() {
+        measure(
+            &mut store,
+            &AsyncServiceDiagnosticsPlugin::LATENCY,
+            latency.as_secs_f64() * 1000.0,
+        );
+    }
+}
+
+fn measure(store: &mut DiagnosticsStore, path: &DiagnosticPath, value: f64) {
+    if let Some(diagnostic) = store.get_mut(path).filter(|d| d.is_enabled) {
+        diagnostic.add_measurement(DiagnosticMeasurement {
+            time: Instant::now(),
+            value,
+        });
+    }
+}
+
+#[cfg(feature = "debug_overlay")]
+pub use overlay::AsyncServiceOverlayPlugin;
+
+#[cfg(feature = "debug_overlay")]
+mod overlay {
+    use std::fmt::Write as _;
+
+    use super::*;
+
+    /// Shows all async bridge diagnostics in the top left corner.
+    /// Requires the [`AsyncServiceDiagnosticsPlugin`].
+    pub struct AsyncServiceOverlayPlugin;
+
+    impl Plugin for AsyncServiceOverlayPlugin {
+        fn build(&self, app: &mut App) {
+            app.add_systems(Startup, spawn_overlay)
+                .add_systems(Last, update_overlay.after(record_diagnostics));
+        }
+    }
+
+    #[derive(Component)]
+    struct BridgeOverlay;
+
+    fn spawn_overlay(mut commands: Commands) {
+        commands.spawn((
+            BridgeOverlay,
+            Text::default(),
+            TextFont {
+                font_size: 14.0,
+                ..default()
+            },
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+        ));
+    }
+
+    fn update_overlay(
+        store: Res<DiagnosticsStore>,
+        mut text: Single<&mut Text, With<BridgeOverlay>>,
+    ) {
+        let mut content = String::new();
+        store
+            .iter()
+            .filter(|d| d.path().as_str().starts_with("async_service/"))
+            .for_each(|d| {
+                let value = d.smoothed().unwrap_or_default();
+                _ = writeln!(content, "{}: {value:.2}{}", d.path(), d.suffix);
+            });
+        text.0 = content;
+    }
+}
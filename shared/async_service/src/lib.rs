@@ -1,9 +1,23 @@
+mod derived;
+pub mod diagnostics;
+mod streams;
+#[cfg(feature = "testing")]
+pub mod testing;
+
 use std::iter;
+use std::sync::Arc;
 
 use async_channel::Receiver;
 use async_channel::Sender;
 use bevy::ecs::system::SystemId;
+use bevy::platform::collections::HashMap;
+use bevy::platform::time::Instant;
 use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
+pub use derived::*;
+use diagnostics::*;
+pub use streams::WorldStream;
+use streams::*;
 
 pub struct AsyncServicePlugin;
 
@@ -12,14 +26,25 @@ impl Plugin for AsyncServicePlugin {
         let (service, service_rx) = new_async_service();
         app.insert_resource(service)
             .insert_resource(service_rx)
-            .init_resource::<Processors>()
-            .add_systems(Update, execute_processors);
+            .init_resource::<BatchFlushes>()
+            .init_resource::<StreamFeeders>()
+            .init_resource::<BridgeStats>()
+            .add_systems(Update, (execute_jobs, feed_streams).chain());
     }
 }
 
+/// Work sent from the async side, executed with exclusive world access on the next update
+struct Job {
+    /// type name of the system the job runs, jobs are counted per bridge in the [`BridgeStats`]
+    bridge: &'static str,
+    run: Box<dyn FnOnce(&mut World) + Send + Sync>,
+}
+
 #[derive(Resource, Clone)]
 pub struct AsyncService {
-    registration: Sender<Box<dyn FnOnce(&mut World) + Send + Sync>>,
+    jobs: Sender<Job>,
+    queued: Arc<QueuedJobs>,
+    latency: Arc<LatencyStats>,
 }
 
 impl AsyncService {
@@ -30,115 +55,181 @@ impl AsyncService {
         O: Send + Sync + 'static,
         S: IntoSystem<In<I>, O, M> + Send + Sync + 'static,
     {
-        let (tx, rx) = async_channel::bounded::<(SystemId<In<I>, O>, SystemBridgeAsync<I, O>)>(1);
-        self.registration
-            .send(Box::new(move |world| {
-                let sys = world.register_system_cached(system);
-                if !world.is_resource_added::<BridgeMarker<I, O>>() {
-                    world.init_resource::<BridgeMarker<I, O>>();
-                    world
-                        .resource_mut::<Processors>()
-                        .0
-                        .push(Box::new(try_exec_processor::<I, O>));
-                    let req = async_channel::unbounded::<(SystemId<In<I>, O>, I)>();
-                    let rsp = async_channel::unbounded::<O>();
-                    let bridge_async = SystemBridgeAsync {
-                        req: req.0,
-                        rsp: rsp.1,
-                    };
-                    world.insert_resource(bridge_async.clone());
-                    world.insert_resource(SystemBridgeSync {
-                        req: req.1,
-                        rsp: rsp.0,
-                    });
-                    tx.send_blocking((sys, bridge_async)).unwrap();
-                } else {
-                    tx.send_blocking((sys, world.resource::<SystemBridgeAsync<I, O>>().clone()))
-                        .unwrap();
-                }
-            }))
+        let start = Instant::now();
+        let (tx, rx) = async_channel::bounded::<O>(1);
+        self.send_job::<S>(move |world| {
+            let sys = world.register_system_cached(system);
+            let output = world.run_system_with(sys, input).unwrap();
+            // the requesting future may have been dropped in the meantime
+            _ = tx.try_send(output);
+        })
+        .await;
+
+        let output = rx.recv().await.unwrap();
+        self.latency.record(start.elapsed());
+        output
+    }
+
+    /// Runs `extract` as a sync system to copy data out of the world,
+    /// then runs `compute` on the [`AsyncComputeTaskPool`] so heavy work stays off the main thread.
+    /// Use [`Self::exec_sync`] again to write the result back into the world.
+    pub async fn exec_compute<I, D, R, M, S, F>(&self, extract: S, input: I, compute: F) -> R
+    where
+        I: Send + Sync + 'static,
+        D: Send + Sync + 'static,
+        R: Send + 'static,
+        S: IntoSystem<In<I>, D, M> + Send + Sync + 'static,
+        F: FnOnce(D) -> R + Send + 'static,
+    {
+        let data = self.exec_sync(extract, input).await;
+        AsyncComputeTaskPool::get()
+            .spawn(async move { compute(data) })
+            .await
+    }
+
+    /// Like [`Self::exec_sync`], but all inputs queued for the same system until the next update
+    /// are collected and passed to a single run of the system.
+    /// The system has to return exactly one output per input, in the same order.
+    ///
+    /// # Panics
+    ///
+    /// In the calling task if the system returned a different number of outputs, which is also logged as an error.
+    pub async fn exec_sync_batched<I, O, M, S>(&self, system: S, input: I) -> O
+    where
+        I: Send + Sync + 'static,
+        O: Send + Sync + 'static,
+        S: IntoSystem<In<Vec<I>>, Vec<O>, M> + Send + Sync + 'static,
+    {
+        let start = Instant::now();
+        let (tx, rx) = async_channel::bounded::<O>(1);
+        self.send_job::<S>(move |world| {
+            let sys = world.register_system_cached(system);
+            if !world.contains_resource::<PendingBatches<I, O>>() {
+                world.insert_resource(PendingBatches::<I, O>(default()));
+            }
+            let mut pending = world.resource_mut::<PendingBatches<I, O>>();
+            let first_in_update = pending.0.is_empty();
+            let (inputs, responders) = pending.0.entry(sys).or_default();
+            inputs.push(input);
+            responders.push(tx);
+            let mut flushes = world.resource_mut::<BatchFlushes>();
+            flushes.pending += 1;
+            if first_in_update {
+                flushes.flushes.push(flush_batches::<I, O>);
+            }
+        })
+        .await;
+
+        let output = rx
+            .recv()
             .await
-            .unwrap();
+            .expect("batched system did not return one output per input");
+        self.latency.record(start.elapsed());
+        output
+    }
 
-        let (sys, bridge) = rx.recv().await.unwrap();
-        bridge.req.send((sys, input)).await.unwrap();
-        bridge.rsp.recv().await.unwrap()
+    /// Queues `run` for the next update
+    async fn send_job<S>(&self, run: impl FnOnce(&mut World) + Send + Sync + 'static) {
+        self.jobs.send(self.job::<S>(run)).await.unwrap();
+    }
+
+    /// Wraps `run` into a job of the bridge of system `S`
+    fn job<S>(&self, run: impl FnOnce(&mut World) + Send + Sync + 'static) -> Job {
+        let bridge = std::any::type_name::<S>();
+        self.queued.push(bridge);
+        Job {
+            bridge,
+            run: Box::new(run),
+        }
     }
 }
 
 #[derive(Resource)]
 struct AsyncServiceRx {
-    registration: Receiver<Box<dyn FnOnce(&mut World) + Send + Sync>>,
+    jobs: Receiver<Job>,
+    queued: Arc<QueuedJobs>,
 }
 
 fn new_async_service() -> (AsyncService, AsyncServiceRx) {
-    let (tx, rx) = async_channel::unbounded::<Box<dyn FnOnce(&mut World) + Send + Sync>>();
+    let (tx, rx) = async_channel::unbounded::<Job>();
+    let queued = Arc::<QueuedJobs>::default();
     (
-        AsyncService { registration: tx },
-        AsyncServiceRx { registration: rx },
+        AsyncService {
+            jobs: tx,
+            queued: queued.clone(),
+            latency: default(),
+        },
+        AsyncServiceRx { jobs: rx, queued },
     )
 }
 
-#[derive(Resource)]
-struct BridgeMarker<I, O> {
-    _marker: std::marker::PhantomData<(I, O)>,
-}
-
-impl<I, O> Default for BridgeMarker<I, O> {
-    fn default() -> Self {
-        Self {
-            _marker: std::marker::PhantomData,
-        }
-    }
-}
+/// System that handles all inputs queued for it in one run
+type BatchSystem<I, O> = SystemId<In<Vec<I>>, Vec<O>>;
 
-/// Async Side
-#[derive(Resource)]
-struct SystemBridgeAsync<I: 'static, O> {
-    req: Sender<(SystemId<In<I>, O>, I)>,
-    rsp: Receiver<O>,
-}
-
-impl<I: 'static, O> Clone for SystemBridgeAsync<I, O> {
-    fn clone(&self) -> Self {
-        Self {
-            req: self.req.clone(),
-            rsp: self.rsp.clone(),
-        }
-    }
-}
+/// Queued inputs of one batch system and the responders waiting for their outputs
+type Batch<I, O> = (Vec<I>, Vec<Sender<O>>);
 
-/// Sync Sid
+/// Inputs of batched requests, collected per system until the jobs of the current update ran
 #[derive(Resource)]
-struct SystemBridgeSync<I: 'static, O> {
-    req: Receiver<(SystemId<In<I>, O>, I)>,
-    rsp: Sender<O>,
+struct PendingBatches<I: 'static, O: 'static>(HashMap<BatchSystem<I, O>, Batch<I, O>>);
+
+/// Batch types that received inputs in the current update
+#[derive(Resource, Default)]
+struct BatchFlushes {
+    flushes: Vec<fn(&mut World) -> usize>,
+    /// inputs over all batch types that were not flushed yet
+    pending: usize,
 }
 
-fn try_exec_processor<In, Out>(world: &mut World)
+/// Runs every pending batch of one type, returns the number of inputs that were pending
+fn flush_batches<I, O>(world: &mut World) -> usize
 where
-    In: Send + Sync + 'static,
-    Out: Send + Sync + 'static,
+    I: Send + Sync + 'static,
+    O: Send + Sync + 'static,
 {
-    world.resource_scope::<SystemBridgeSync<In, Out>, _>(|world, bridge| {
-        while let Ok((sys, input)) = bridge.req.try_recv() {
-            let output = world.run_system_with(sys, input).unwrap();
-            bridge.rsp.send_blocking(output).unwrap();
+    let batches = std::mem::take(&mut world.resource_mut::<PendingBatches<I, O>>().0);
+    let pending = batches.values().map(|(inputs, _)| inputs.len()).sum();
+    world.resource_mut::<BatchFlushes>().pending -= pending;
+    for (sys, (inputs, responders)) in batches {
+        let outputs = world.run_system_with(sys, inputs).unwrap();
+        // dropping the responders fails the callers of this batch only
+        if outputs.len() != responders.len() {
+            error!(
+                "batched system {} returned {} outputs for {} inputs",
+                ShortName::of::<BatchSystem<I, O>>(),
+                outputs.len(),
+                responders.len()
+            );
+            continue;
         }
-    });
+        outputs
+            .into_iter()
+            .zip(responders)
+            .for_each(|(output, rsp)| _ = rsp.try_send(output));
+    }
+    pending
 }
 
-#[derive(Resource, Default, Deref)]
-struct Processors(Vec<Box<dyn Fn(&mut World) + Send + Sync>>);
-
-fn execute_processors(world: &mut World) {
+fn execute_jobs(world: &mut World) {
+    let mut stats = BridgeStats::default();
     world.resource_scope::<AsyncServiceRx, _>(|world, async_service| {
-        iter::from_fn(|| async_service.registration.try_recv().ok()).for_each(|reg| reg(world));
-    });
+        // jobs queued while these run have to wait for the next update
+        let queued = async_service.jobs.len();
+        for job in iter::from_fn(|| async_service.jobs.try_recv().ok()).take(queued) {
+            async_service.queued.pop(job.bridge);
+            stats.executed += 1;
+            stats.bridges.entry(job.bridge).or_default().executed += 1;
+            (job.run)(world);
+        }
 
-    world.resource_scope::<Processors, _>(|world, processors| {
-        for proc in processors.0.iter() {
-            proc(world);
+        let flushes = std::mem::take(&mut world.resource_mut::<BatchFlushes>().flushes);
+        stats.batched = flushes.into_iter().map(|flush| flush(world)).sum();
+
+        stats.queued = async_service.jobs.len() + world.resource::<BatchFlushes>().pending;
+        for (bridge, queued) in async_service.queued.snapshot() {
+            stats.bridges.entry(bridge).or_default().queued = queued;
         }
-    })
+        stats.processors = stats.bridges.values().filter(|b| b.queued > 0).count();
+    });
+    world.insert_resource(stats);
 }
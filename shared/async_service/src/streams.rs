@@ -0,0 +1,115 @@
+use async_channel::Sender;
+use bevy::ecs::query::QueryFilter;
+use bevy::ecs::system::BoxedSystem;
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::Stream;
+
+use crate::AsyncService;
+
+/// Streams returned by [`AsyncService`], can be polled with `StreamExt::next` from async code
+pub type WorldStream<T> = std::pin::Pin<Box<dyn Stream<Item = T> + Send>>;
+
+impl AsyncService {
+    /// Yields every `C` added to an entity matching `F` after the stream was created
+    pub fn stream_added<C, F>(&self) -> WorldStream<(Entity, C)>
+    where
+        C: Component + Clone,
+        F: QueryFilter + 'static,
+    {
+        self.stream(|tx| {
+            move |query: Query<(Entity, &C), (Added<C>, F)>| {
+                query
+                    .iter()
+                    .for_each(|(entity, c)| _ = tx.try_send((entity, c.clone())));
+                !tx.is_closed()
+            }
+        })
+    }
+
+    /// Yields every `C` added to or mutated on an entity matching `F` after the stream was created
+    pub fn stream_changed<C, F>(&self) -> WorldStream<(Entity, C)>
+    where
+        C: Component + Clone,
+        F: QueryFilter + 'static,
+    {
+        self.stream(|tx| {
+            move |query: Query<(Entity, &C), (Changed<C>, F)>| {
+                query
+                    .iter()
+                    .for_each(|(entity, c)| _ = tx.try_send((entity, c.clone())));
+                !tx.is_closed()
+            }
+        })
+    }
+
+    /// Yields every entity `C` got removed from.
+    /// Removals that are still buffered when the stream is created are included.
+    pub fn stream_removed<C: Component>(&self) -> WorldStream<Entity> {
+        self.stream(|tx| {
+            move |mut removed: RemovedComponents<C>| {
+                removed.read().for_each(|entity| _ = tx.try_send(entity));
+                !tx.is_closed()
+            }
+        })
+    }
+
+    /// Yields every message of type `M`.
+    /// Messages that are still buffered when the stream is created are included.
+    /// Ends with a warning if `M` was never added to the app.
+    pub fn stream_messages<M: Message + Clone>(&self) -> WorldStream<M> {
+        self.stream(|tx| {
+            move |mut messages: MessageReader<M>| {
+                messages.read().for_each(|m| _ = tx.try_send(m.clone()));
+                !tx.is_closed()
+            }
+        })
+    }
+
+    /// Yields the new value of `R` whenever it was inserted or mutated after the stream was created
+    pub fn stream_resource<R: Resource + Clone>(&self) -> WorldStream<R> {
+        self.stream(|tx| {
+            move |res: Option<Res<R>>| {
+                if let Some(res) = res.filter(|res| res.is_changed()) {
+                    _ = tx.try_send(res.clone());
+                }
+                !tx.is_closed()
+            }
+        })
+    }
+
+    /// Registers a feeder system that forwards world data into the returned stream.
+    /// The feeder returns `false` once the stream was dropped and is then removed.
+    fn stream<T, M, S>(&self, make_feeder: impl FnOnce(Sender<T>) -> S) -> WorldStream<T>
+    where
+        T: Send + 'static,
+        S: IntoSystem<(), bool, M> + Send + Sync + 'static,
+    {
+        let (tx, rx) = async_channel::unbounded::<T>();
+        let feeder = make_feeder(tx);
+        let job = self.job::<S>(move |world| {
+            let mut feeder: BoxedSystem<(), bool> = Box::new(IntoSystem::into_system(feeder));
+            feeder.initialize(world);
+            // only report changes that happen from now on
+            feeder.set_last_run(world.change_tick());
+            world.resource_mut::<StreamFeeders>().0.push(feeder);
+        });
+        self.jobs.send_blocking(job).unwrap();
+        Box::pin(rx)
+    }
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct StreamFeeders(Vec<BoxedSystem<(), bool>>);
+
+pub(crate) fn feed_streams(world: &mut World) {
+    world.resource_scope::<StreamFeeders, _>(|world, mut feeders| {
+        feeders.0.retain_mut(|feeder| match feeder.run((), world) {
+            Ok(open) => open,
+            // e.g. the messages of a stream were never registered, dropping the feeder ends it
+            Err(error) => {
+                warn!("ending the stream fed by {}: {error}", feeder.name());
+                false
+            }
+        });
+    });
+}
@@ -0,0 +1,129 @@
+//! Headless app with a deterministic single threaded executor, to drive the async bridge frame by frame
+
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Wake;
+use std::task::Waker;
+
+use bevy::prelude::*;
+
+use crate::AsyncService;
+use crate::AsyncServicePlugin;
+
+/// Minimal [`App`] with the [`AsyncServicePlugin`], frames only advance when calling [`Self::update`].
+/// Futures spawned here are polled on the calling thread in spawn order, so results are reproducible.
+pub struct AsyncTestApp {
+    pub app: App,
+    tasks: Vec<LocalTask>,
+}
+
+impl Default for AsyncTestApp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AsyncTestApp {
+    pub fn new() -> Self {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AsyncServicePlugin));
+        Self {
+            app,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn service(&self) -> AsyncService {
+        self.app.world().resource::<AsyncService>().clone()
+    }
+
+    /// Polls `future` right away and then whenever it got woken, see [`Self::update`]
+    pub fn spawn<T: 'static>(&mut self, future: impl Future<Output = T> + 'static) -> Spawned<T> {
+        let output = Rc::new(RefCell::new(None));
+        let future = {
+            let output = output.clone();
+            async move { *output.borrow_mut() = Some(future.await) }
+        };
+        self.tasks.push(LocalTask {
+            future: Box::pin(future),
+            woken: Arc::new(Woken(AtomicBool::new(true))),
+        });
+        self.run_until_stalled();
+        Spawned(output)
+    }
+
+    /// Runs the spawned futures until none of them can progress, then one frame of the app,
+    /// then the futures again
+    pub fn update(&mut self) {
+        self.run_until_stalled();
+        self.app.update();
+        self.run_until_stalled();
+    }
+
+    pub fn update_n(&mut self, frames: usize) {
+        (0..frames).for_each(|_| self.update());
+    }
+
+    /// Updates until `spawned` resolved, returns the output and the number of updates it took
+    pub fn update_until<T>(&mut self, spawned: &Spawned<T>, max_frames: usize) -> (T, usize) {
+        for frame in 0..=max_frames {
+            if let Some(output) = spawned.take() {
+                return (output, frame);
+            }
+            self.update();
+        }
+        panic!("future did not resolve within {max_frames} updates");
+    }
+
+    fn run_until_stalled(&mut self) {
+        loop {
+            let mut progressed = false;
+            self.tasks.retain_mut(|task| {
+                if !task.woken.0.swap(false, Ordering::AcqRel) {
+                    return true;
+                }
+                progressed = true;
+                let waker = Waker::from(task.woken.clone());
+                task.future
+                    .as_mut()
+                    .poll(&mut Context::from_waker(&waker))
+                    .is_pending()
+            });
+            if !progressed {
+                break;
+            }
+        }
+    }
+}
+
+/// Output slot of a future spawned on an [`AsyncTestApp`]
+pub struct Spawned<T>(Rc<RefCell<Option<T>>>);
+
+impl<T> Spawned<T> {
+    pub fn is_finished(&self) -> bool {
+        self.0.borrow().is_some()
+    }
+
+    /// Takes the output if the future resolved
+    pub fn take(&self) -> Option<T> {
+        self.0.borrow_mut().take()
+    }
+}
+
+struct LocalTask {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    woken: Arc<Woken>,
+}
+
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
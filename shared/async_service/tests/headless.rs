@@ -0,0 +1,270 @@
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+use async_service::diagnostics::AsyncServiceDiagnosticsPlugin;
+use async_service::testing::AsyncTestApp;
+use async_service::*;
+use bevy::diagnostic::DiagnosticPath;
+use bevy::diagnostic::DiagnosticsStore;
+use bevy::ecs::system::RunSystemOnce;
+use bevy::prelude::*;
+use bevy::tasks::block_on;
+use bevy::tasks::futures_lite::FutureExt;
+use bevy::tasks::futures_lite::StreamExt;
+use bevy::tasks::futures_lite::future::poll_once;
+
+#[derive(Resource, Default)]
+struct BatchRuns(u32);
+
+#[derive(Resource, Clone, Default, Debug, PartialEq)]
+struct Score(u32);
+
+#[derive(Message, Clone)]
+struct Unregistered;
+
+#[derive(Asset, TypePath, Clone)]
+struct Source(u32);
+
+#[derive(Asset, TypePath)]
+struct Target(u32);
+
+fn double(In(x): In<u32>) -> u32 {
+    x * 2
+}
+
+fn double_all(In(xs): In<Vec<u32>>, mut runs: ResMut<BatchRuns>) -> Vec<u32> {
+    runs.0 += 1;
+    xs.into_iter().map(|x| x * 2).collect()
+}
+
+fn drop_first(In(xs): In<Vec<u32>>) -> Vec<u32> {
+    xs.into_iter().skip(1).collect()
+}
+
+/// Sends a job while the bridge is executing, the response is never awaited
+fn requeue(In(x): In<u32>, service: Res<AsyncService>) -> u32 {
+    _ = block_on(poll_once(service.exec_sync(double, x)));
+    x
+}
+
+fn measurement(test: &AsyncTestApp, path: &DiagnosticPath) -> Option<f64> {
+    let store = test.app.world().resource::<DiagnosticsStore>();
+    store.get_measurement(path).map(|m| m.value)
+}
+
+#[test]
+fn exec_sync_is_answered_by_the_next_update() {
+    let mut test = AsyncTestApp::new();
+    let service = test.service();
+
+    let doubled = test.spawn(async move { service.exec_sync(double, 21).await });
+    assert_eq!(test.update_until(&doubled, 10), (42, 1));
+}
+
+#[test]
+fn concurrent_callers_get_their_own_response() {
+    let mut test = AsyncTestApp::new();
+    let service = test.service();
+
+    let concurrent = [1, 2, 3].map(|x| {
+        let service = service.clone();
+        test.spawn(async move { service.exec_sync(double, x).await })
+    });
+    test.update();
+    assert_eq!(concurrent.map(|c| c.take()), [Some(2), Some(4), Some(6)]);
+}
+
+#[test]
+fn batched_callers_share_one_run() {
+    let mut test = AsyncTestApp::new();
+    test.app
+        .add_plugins(AsyncServiceDiagnosticsPlugin)
+        .init_resource::<BatchRuns>();
+    let service = test.service();
+
+    let batched = [1, 2, 3].map(|x| {
+        let service = service.clone();
+        test.spawn(async move { service.exec_sync_batched(double_all, x).await })
+    });
+    test.update();
+    assert_eq!(batched.map(|b| b.take()), [Some(2), Some(4), Some(6)]);
+    assert_eq!(test.app.world().resource::<BatchRuns>().0, 1);
+
+    // the three jobs ran and passed their inputs to one batch, nothing is left in the queue
+    let bridge = AsyncServiceDiagnosticsPlugin::bridge_executed(&double_all);
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::QUEUED),
+        Some(0.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::EXECUTED),
+        Some(3.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::BATCHED),
+        Some(3.0)
+    );
+    assert_eq!(measurement(&test, &bridge), Some(3.0));
+
+    test.update();
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::EXECUTED),
+        Some(0.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::BATCHED),
+        Some(0.0)
+    );
+    assert_eq!(measurement(&test, &bridge), Some(0.0));
+}
+
+#[test]
+fn mismatched_batches_only_fail_their_callers() {
+    let mut test = AsyncTestApp::new();
+    let service = test.service();
+
+    let failed = {
+        let service = service.clone();
+        test.spawn(async move {
+            AssertUnwindSafe(service.exec_sync_batched(drop_first, 1))
+                .catch_unwind()
+                .await
+                .is_err()
+        })
+    };
+    let doubled = test.spawn(async move { service.exec_sync(double, 2).await });
+    test.update();
+    assert_eq!(failed.take(), Some(true));
+    assert_eq!(doubled.take(), Some(4));
+}
+
+#[test]
+fn jobs_queued_during_an_update_wait_for_the_next() {
+    let mut test = AsyncTestApp::new();
+    test.app.add_plugins(AsyncServiceDiagnosticsPlugin);
+    let service = test.service();
+
+    let requeued = test.spawn(async move { service.exec_sync(requeue, 1).await });
+    test.update();
+    assert_eq!(requeued.take(), Some(1));
+    let queued = AsyncServiceDiagnosticsPlugin::bridge_queued(&double);
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::QUEUED),
+        Some(1.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::EXECUTED),
+        Some(1.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::PROCESSORS),
+        Some(1.0)
+    );
+    assert_eq!(measurement(&test, &queued), Some(1.0));
+
+    test.update();
+    let executed = AsyncServiceDiagnosticsPlugin::bridge_executed(&double);
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::QUEUED),
+        Some(0.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::EXECUTED),
+        Some(1.0)
+    );
+    assert_eq!(
+        measurement(&test, &AsyncServiceDiagnosticsPlugin::PROCESSORS),
+        Some(0.0)
+    );
+    assert_eq!(measurement(&test, &queued), Some(0.0));
+    assert_eq!(measurement(&test, &executed), Some(1.0));
+}
+
+#[test]
+fn exec_compute_runs_on_the_compute_pool() {
+    let mut test = AsyncTestApp::new();
+    let service = test.service();
+
+    // the extracted value is processed on the compute pool, which is ticked by the update
+    let computed = test.spawn(async move { service.exec_compute(double, 4, |x| x + 1).await });
+    assert_eq!(test.update_until(&computed, 10).0, 9);
+}
+
+#[test]
+fn streams_only_report_later_changes() {
+    let mut test = AsyncTestApp::new();
+    test.app.init_resource::<Score>();
+    let service = test.service();
+
+    let mut scores = service.stream_resource::<Score>();
+    let next_score = test.spawn(async move { scores.next().await });
+    test.update();
+    assert!(!next_score.is_finished());
+
+    test.app.world_mut().resource_mut::<Score>().0 = 5;
+    test.update();
+    assert_eq!(next_score.take(), Some(Some(Score(5))));
+}
+
+#[test]
+fn streams_of_unregistered_messages_end() {
+    let mut test = AsyncTestApp::new();
+    let service = test.service();
+
+    let mut messages = service.stream_messages::<Unregistered>();
+    let next_message = test.spawn(async move { messages.next().await.is_none() });
+    test.update();
+    assert_eq!(next_message.take(), Some(true));
+}
+
+#[test]
+fn derived_assets_follow_their_source() {
+    let mut test = AsyncTestApp::new();
+    test.app
+        .add_plugins(AssetPlugin::default())
+        .init_asset::<Source>()
+        .init_asset::<Target>()
+        .add_derived_asset(clone_source, |source: Source| Target(source.0 * 2));
+
+    let source = test
+        .app
+        .world_mut()
+        .resource_mut::<Assets<Source>>()
+        .add(Source(1));
+    let source_id = source.id();
+    let target = test
+        .app
+        .world_mut()
+        .run_system_once(move |mut derive: DeriveAssets<Source, Target>| {
+            derive.derive(source.clone())
+        })
+        .unwrap();
+    let derived = |test: &AsyncTestApp| {
+        let targets = test.app.world().resource::<Assets<Target>>();
+        targets.get(&target).map(|t| t.0)
+    };
+
+    // the compute part runs on another thread, so give it a few frames
+    let update_until = |test: &mut AsyncTestApp, expected: u32| {
+        for _ in 0..100 {
+            if derived(test) == Some(expected) {
+                return;
+            }
+            test.update();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("derived asset was not updated to {expected}");
+    };
+    update_until(&mut test, 2);
+
+    let mut sources = test.app.world_mut().resource_mut::<Assets<Source>>();
+    sources.get_mut(source_id).unwrap().0 = 5;
+    update_until(&mut test, 10);
+}
+
+fn clone_source(In(h): In<Handle<Source>>, sources: Res<Assets<Source>>) -> Source {
+    sources
+        .get(&h)
+        .expect("requested source not loaded")
+        .clone()
+}
@@ -0,0 +1,36 @@
+use atlas::*;
+use bevy::prelude::*;
+
+/// Draws the assembler as is, tinted and mirrored.
+/// All sprites share one atlas, so they are merged into a single draw call.
+fn main() -> AppExit {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(ImagePlugin::default_nearest()),
+            AtlasPlugin,
+        ))
+        .add_systems(Startup, setup)
+        .run()
+}
+
+fn setup(asset_server: Res<AssetServer>, mut commands: Commands) {
+    commands.spawn((Camera2d, Transform::from_scale(Vec3::splat(0.25))));
+
+    let assembler: Handle<AtlasEntry> =
+        asset_server.load("tile_entities/assembler.meta.yml#assembler");
+
+    for (x, color, flip_x) in [
+        (-80.0, Color::WHITE, false),
+        (0.0, Color::srgb(1.0, 0.6, 0.6), false),
+        (80.0, Color::WHITE, true),
+    ] {
+        commands.spawn((
+            AtlasSprite {
+                color,
+                flip_x,
+                ..AtlasSprite::new(assembler.clone())
+            },
+            Transform::from_xyz(x, 0.0, 0.0),
+        ));
+    }
+}
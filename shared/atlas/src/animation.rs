@@ -0,0 +1,300 @@
+//! Playback of [`AnimationDefinition`]s, see [`AtlasAnimator`]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use crate::*;
+
+pub(crate) struct AtlasAnimationPlugin;
+
+impl Plugin for AtlasAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AtlasClocks>()
+            .add_systems(Update, animate_sprites);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayMode {
+    #[default]
+    Loop,
+    /// Stops on the last frame
+    Once,
+    /// Plays forwards and backwards again, without repeating the first and last frame
+    PingPong,
+}
+
+impl PlayMode {
+    /// Maps the number of frames played so far to a frame of the animation, and whether it finished
+    fn frame(self, played: u32, count: u32) -> (u32, bool) {
+        let count = count.max(1);
+        match self {
+            PlayMode::Loop => (played % count, false),
+            PlayMode::Once => (played.min(count - 1), played >= count),
+            PlayMode::PingPong => {
+                let period = self.period(count);
+                let frame = played % period;
+                (frame.min(period - frame), false)
+            }
+        }
+    }
+
+    /// Frames played until the animation repeats
+    fn period(self, count: u32) -> u32 {
+        let count = count.max(1);
+        match self {
+            PlayMode::Loop | PlayMode::Once => count,
+            PlayMode::PingPong => (2 * count - 2).max(1),
+        }
+    }
+
+    /// Frames entered in order while the frames played advance from `from` to `to`.
+    /// Covers at most one period, so a frame is entered at most once per update.
+    fn frames_entered(self, from: u32, to: u32, count: u32) -> Vec<u32> {
+        // frames played past the end of a single run don't enter anything
+        let to = match self {
+            PlayMode::Once => to.min(count),
+            PlayMode::Loop | PlayMode::PingPong => to,
+        };
+        let from = from.min(to).max(to.saturating_sub(self.period(count)));
+        let mut last = self.frame(from, count).0;
+        (from + 1..=to)
+            .map(|played| self.frame(played, count).0)
+            .filter(|frame| {
+                let entered = *frame != last;
+                last = *frame;
+                entered
+            })
+            .collect()
+    }
+}
+
+/// Plays the animation of the entry of the [`AtlasSprite`] on the same entity by setting its `state.animation`.
+/// Selecting another clip with `state.clip` restarts it.
+#[derive(Component, Debug, Clone)]
+pub struct AtlasAnimator {
+    pub mode: PlayMode,
+    /// Playback speed multiplier, also applied on top of a shared clock
+    pub speed: f32,
+    pub paused: bool,
+    /// Name of an [`AtlasClocks`] clock to follow instead of the own time,
+    /// so all animators using it stay in lockstep
+    pub clock: Option<Arc<str>>,
+    /// Frames that trigger an [`AtlasFrameReached`] whenever the animator enters them,
+    /// including the ones passed within a single update
+    pub frame_events: Vec<u32>,
+    elapsed: Duration,
+    frame: Option<u32>,
+    /// Frames played at the last update, to find the frames passed since
+    played: Option<u32>,
+    finished: bool,
+    /// Clip the animator played last, to notice clip changes
+    clip: Option<Arc<str>>,
+}
+
+impl AtlasAnimator {
+    pub fn new(mode: PlayMode) -> Self {
+        Self {
+            mode,
+            speed: 1.0,
+            paused: false,
+            clock: None,
+            frame_events: Vec::new(),
+            elapsed: Duration::ZERO,
+            frame: None,
+            played: None,
+            finished: false,
+            clip: None,
+        }
+    }
+
+    /// Loops in lockstep with all other animators on `clock`
+    pub fn synced(clock: impl Into<Arc<str>>) -> Self {
+        Self {
+            clock: Some(clock.into()),
+            ..Self::new(PlayMode::Loop)
+        }
+    }
+
+    pub fn with_frame_events(mut self, frames: impl IntoIterator<Item = u32>) -> Self {
+        self.frame_events.extend(frames);
+        self
+    }
+
+    /// Starts again from the first frame, has no effect when following a clock
+    pub fn restart(&mut self) {
+        self.elapsed = Duration::ZERO;
+        self.frame = None;
+        self.played = None;
+        self.finished = false;
+    }
+
+    /// Animation time, already scaled by the speed
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Frame shown right now, `None` until the entry has been loaded
+    pub fn frame(&self) -> Option<u32> {
+        self.frame
+    }
+
+    /// Whether a [`PlayMode::Once`] animation reached its end
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Default for AtlasAnimator {
+    fn default() -> Self {
+        Self::new(PlayMode::Loop)
+    }
+}
+
+/// Fired on an entity when its [`AtlasAnimator`] enters one of its `frame_events`
+#[derive(EntityEvent, Debug, Clone)]
+pub struct AtlasFrameReached {
+    #[event_target]
+    pub entity: Entity,
+    pub frame: u32,
+}
+
+/// Named clocks shared between [`AtlasAnimator`]s, created on first use
+#[derive(Resource, Debug, Default)]
+pub struct AtlasClocks(HashMap<Arc<str>, AtlasClock>);
+
+#[derive(Debug, Clone)]
+pub struct AtlasClock {
+    pub elapsed: Duration,
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Default for AtlasClock {
+    fn default() -> Self {
+        Self {
+            elapsed: Duration::ZERO,
+            speed: 1.0,
+            paused: false,
+        }
+    }
+}
+
+impl AtlasClocks {
+    pub fn get(&self, name: &str) -> Option<&AtlasClock> {
+        self.0.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> &mut AtlasClock {
+        self.0.entry(Arc::from(name)).or_default()
+    }
+
+    fn advance(&mut self, delta: Duration) {
+        self.0
+            .values_mut()
+            .filter(|clock| !clock.paused)
+            .for_each(|clock| clock.elapsed += delta.mul_f32(clock.speed.max(0.0)));
+    }
+}
+
+fn animate_sprites(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut clocks: ResMut<AtlasClocks>,
+    entries: Res<Assets<AtlasEntry>>,
+    mut sprites: Query<(Entity, &mut AtlasAnimator, &mut AtlasSprite)>,
+) {
+    clocks.advance(time.delta());
+
+    for (entity, mut animator, mut sprite) in &mut sprites {
+        let Some(animation) = entries
+            .get(&sprite.entry)
+            .and_then(|entry| entry.definition.clip(sprite.state.clip.as_deref()))
+        else {
+            continue;
+        };
+        if animator.clip != sprite.state.clip {
+            animator.restart();
+            animator.clip = sprite.state.clip.clone();
+        }
+        if animator.paused {
+            continue;
+        }
+
+        let speed = animator.speed.max(0.0);
+        animator.elapsed = match &animator.clock {
+            Some(clock) => clocks.get_mut(clock).elapsed.mul_f32(speed),
+            None => animator.elapsed + time.delta().mul_f32(speed),
+        };
+
+        let played = animation.frames_played(animator.elapsed);
+        let count = animation.frame_count();
+        let (frame, finished) = animator.mode.frame(played, count);
+        animator.finished = finished;
+
+        // a slower clock or speed can step back, which only enters the frame shown now
+        let entered = match animator.played {
+            Some(last) if last <= played => animator.mode.frames_entered(last, played, count),
+            _ => Vec::from_iter((animator.frame != Some(frame)).then_some(frame)),
+        };
+        animator.played = Some(played);
+        animator.frame = Some(frame);
+        for frame in entered {
+            if animator.frame_events.contains(&frame) {
+                commands.trigger(AtlasFrameReached { entity, frame });
+            }
+        }
+
+        // only touch the sprite when needed, every change rebuilds its batch
+        if sprite.state.animation != frame {
+            sprite.state.animation = frame;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_modes_map_played_frames() {
+        let frames = |mode: PlayMode, count| {
+            (0..8)
+                .map(|played| mode.frame(played, count).0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(frames(PlayMode::Loop, 3), [0, 1, 2, 0, 1, 2, 0, 1]);
+        assert_eq!(frames(PlayMode::Once, 3), [0, 1, 2, 2, 2, 2, 2, 2]);
+        assert_eq!(frames(PlayMode::PingPong, 3), [0, 1, 2, 1, 0, 1, 2, 1]);
+        assert_eq!(frames(PlayMode::PingPong, 1), [0; 8]);
+        assert_eq!(PlayMode::Once.frame(2, 3), (2, false));
+        assert_eq!(PlayMode::Once.frame(3, 3), (2, true));
+    }
+
+    #[test]
+    fn skipped_frames_are_entered_in_order() {
+        let cases = [
+            (PlayMode::Loop, 0, 0, vec![]),
+            (PlayMode::Loop, 0, 1, vec![1]),
+            (PlayMode::Loop, 0, 3, vec![1, 2, 3]),
+            // wraps around the end of the clip
+            (PlayMode::Loop, 3, 6, vec![4, 0, 1]),
+            // several loops at once enter every frame once
+            (PlayMode::Loop, 1, 12, vec![3, 4, 0, 1, 2]),
+            (PlayMode::Once, 2, 9, vec![3, 4]),
+            (PlayMode::Once, 4, 9, vec![]),
+            (PlayMode::PingPong, 2, 7, vec![3, 4, 3, 2, 1]),
+            (PlayMode::PingPong, 6, 20, vec![3, 2, 1, 0, 1, 2, 3, 4]),
+        ];
+        for (mode, from, to, entered) in cases {
+            assert_eq!(
+                mode.frames_entered(from, to, 5),
+                entered,
+                "{mode:?} {from}..{to}"
+            );
+        }
+    }
+}
@@ -0,0 +1,749 @@
+//! Loads `.aseprite` / `.ase` files as atlases, see [`AsepriteLoader`]
+
+use std::collections::BTreeMap;
+use std::io::Read as _;
+use std::time::Duration;
+
+use bevy::asset::AssetLoader;
+use bevy::asset::LoadContext;
+use bevy::asset::RenderAssetUsages;
+use bevy::asset::io::Reader;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+use flate2::read::ZlibDecoder;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::*;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AsepriteSettings {
+    /// Defaults to the canvas size, slices have to be aligned to it
+    pub tile_size: Option<UVec2>,
+    pub stacking: StackingOptions,
+}
+
+/// Flattens all visible layers of every frame into one tile (or more with `tile_size`).
+/// Tags become clips with the frame durations of the file, slices become entries.
+/// Without slices there is a single entry named after the file (`belt.aseprite#belt`).
+///
+/// Tags that don't play forwards get their own row of frames in playback order.
+/// Tilemap layers, blend modes other than normal and the repeat count of tags are ignored.
+#[derive(Default, TypePath)]
+pub struct AsepriteLoader;
+
+impl AssetLoader for AsepriteLoader {
+    type Asset = Atlas;
+    type Settings = AsepriteSettings;
+    type Error = AtlasError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        settings: &AsepriteSettings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Atlas, AtlasError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let file = AsepriteFile::parse(&bytes)?;
+
+        let name = load_context
+            .path()
+            .path()
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let canvas = file.size;
+        let tile_size = settings.tile_size.unwrap_or(canvas);
+        if tile_size.min_element() == 0 || (canvas % tile_size).max_element() != 0 {
+            return Err(AtlasError::PackedImageSize {
+                id: name,
+                size: canvas,
+                tile: tile_size,
+            });
+        }
+        let frame_tiles = canvas / tile_size;
+        let stride = UVec2::new(frame_tiles.x, 0);
+
+        // row 0 holds all frames in order, every other row one tag in playback order
+        let mut rows = vec![(0..file.frames.len()).collect::<Vec<_>>()];
+        let mut clips = BTreeMap::new();
+        for tag in &file.tags {
+            let order = tag.playback_order();
+            let durations = order.iter().map(|&i| file.frames[i].duration).collect();
+            let offset = if tag.direction == 0 {
+                UVec2::new(tag.from as u32 * frame_tiles.x, 0)
+            } else {
+                rows.push(order);
+                UVec2::new(0, (rows.len() as u32 - 1) * frame_tiles.y)
+            };
+            clips.insert(
+                tag.name.clone(),
+                AnimationDefinition::per_frame(offset, stride, durations),
+            );
+        }
+        let animation = (file.frames.len() > 1).then(|| {
+            let durations = file.frames.iter().map(|frame| frame.duration).collect();
+            AnimationDefinition::per_frame(UVec2::ZERO, stride, durations)
+        });
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(1) as u32;
+        let sheet = file.render_sheet(&rows, UVec2::new(columns, rows.len() as u32));
+
+        let entry = |offset: UVec2, size: UVec2| AtlasEntryDefinition {
+            size,
+            offset,
+            animation: animation.clone(),
+            animations: clips.clone(),
+            variants: None,
+            rotation: None,
+            autotile: None,
+            palettes: None,
+        };
+        let entries = match file.slices.is_empty() {
+            true => BTreeMap::from([(name, entry(UVec2::ZERO, frame_tiles))]),
+            false => file
+                .slices
+                .iter()
+                .map(|slice| {
+                    let aligned = (slice.position % tile_size).max_element() == 0
+                        && (slice.size % tile_size).max_element() == 0;
+                    if !aligned || slice.size.min_element() == 0 {
+                        return Err(AtlasError::PackedImageSize {
+                            id: slice.name.clone(),
+                            size: slice.size,
+                            tile: tile_size,
+                        });
+                    }
+                    let definition = entry(slice.position / tile_size, slice.size / tile_size);
+                    Ok((slice.name.clone(), definition))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+
+        build_atlas(
+            load_context,
+            stack_atlas(&sheet, tile_size, &settings.stacking, default())?,
+            tile_size,
+            settings.stacking.extrude,
+            entries,
+        )
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["aseprite", "ase"]
+    }
+}
+
+struct AsepriteFile {
+    size: UVec2,
+    /// 32: RGBA, 16: grayscale with alpha, 8: indexed
+    color_depth: u16,
+    transparent_index: u8,
+    palette: Vec<[u8; 4]>,
+    layers: Vec<Layer>,
+    frames: Vec<Frame>,
+    tags: Vec<Tag>,
+    slices: Vec<Slice>,
+}
+
+struct Layer {
+    /// Also false if any parent group is hidden
+    visible: bool,
+    opacity: u8,
+    is_image: bool,
+}
+
+struct Frame {
+    duration: Duration,
+    cels: Vec<Cel>,
+}
+
+struct Cel {
+    layer: usize,
+    position: IVec2,
+    opacity: u8,
+    z_index: i16,
+    content: CelContent,
+}
+
+enum CelContent {
+    Pixels {
+        size: UVec2,
+        data: Vec<u8>,
+    },
+    /// Same pixels as the cel of the same layer in that frame
+    Linked(usize),
+    Unsupported,
+}
+
+struct Tag {
+    from: usize,
+    to: usize,
+    /// 0: forward, 1: reverse, 2: ping-pong, 3: ping-pong reverse
+    direction: u8,
+    name: String,
+}
+
+impl Tag {
+    /// Frames of one loop of the tag
+    fn playback_order(&self) -> Vec<usize> {
+        let forward = (self.from..=self.to).collect::<Vec<_>>();
+        let backward = forward.iter().rev().copied().collect::<Vec<_>>();
+        // ping-pong tags of one or two frames have no inner frames to play twice
+        let inner = |frames: &[usize]| {
+            frames
+                .get(1..frames.len().saturating_sub(1))
+                .unwrap_or_default()
+                .to_vec()
+        };
+        match self.direction {
+            1 => backward,
+            2 => [forward.clone(), inner(&backward)].concat(),
+            3 => [backward.clone(), inner(&forward)].concat(),
+            _ => forward,
+        }
+    }
+}
+
+struct Slice {
+    name: String,
+    position: UVec2,
+    size: UVec2,
+}
+
+/// Colors an indexed pixel can refer to
+const MAX_PALETTE_LEN: usize = 256;
+
+/// Little endian reader over the file, see the aseprite file format spec
+struct Bytes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], AtlasError> {
+        if len > self.data.len() {
+            return Err(AtlasError::Aseprite("unexpected end of file".to_string()));
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, AtlasError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, AtlasError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i16(&mut self) -> Result<i16, AtlasError> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, AtlasError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, AtlasError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, AtlasError> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+impl AsepriteFile {
+    fn parse(data: &[u8]) -> Result<Self, AtlasError> {
+        let mut bytes = Bytes { data };
+        let mut header = Bytes {
+            data: bytes.take(128)?,
+        };
+        header.u32()?;
+        if header.u16()? != 0xA5E0 {
+            return Err(AtlasError::Aseprite("not an aseprite file".to_string()));
+        }
+        let frame_count = header.u16()?;
+        let size = UVec2::new(header.u16()? as u32, header.u16()? as u32);
+        let color_depth = header.u16()?;
+        let layer_opacity_valid = header.u32()? & 1 != 0;
+        header.take(2 + 4 + 4)?;
+        let transparent_index = header.u8()?;
+
+        let mut file = AsepriteFile {
+            size,
+            color_depth,
+            transparent_index,
+            palette: Vec::new(),
+            layers: Vec::new(),
+            frames: Vec::new(),
+            tags: Vec::new(),
+            slices: Vec::new(),
+        };
+        // visibility of the groups enclosing the next layer, by child level
+        let mut group_visible = Vec::<bool>::new();
+
+        for _ in 0..frame_count {
+            let frame_len = bytes.u32()? as usize;
+            let mut frame = Bytes {
+                data: bytes.take(frame_len.saturating_sub(4))?,
+            };
+            if frame.u16()? != 0xF1FA {
+                return Err(AtlasError::Aseprite("invalid frame header".to_string()));
+            }
+            let old_chunk_count = frame.u16()? as u32;
+            let duration = Duration::from_millis(frame.u16()? as u64);
+            frame.take(2)?;
+            let chunk_count = match frame.u32()? {
+                0 => old_chunk_count,
+                count => count,
+            };
+
+            let mut cels = Vec::new();
+            for _ in 0..chunk_count {
+                let chunk_len = frame.u32()? as usize;
+                let chunk_type = frame.u16()?;
+                let mut chunk = Bytes {
+                    data: frame.take(chunk_len.saturating_sub(6))?,
+                };
+                match chunk_type {
+                    0x2004 => {
+                        let flags = chunk.u16()?;
+                        let layer_type = chunk.u16()?;
+                        let level = chunk.u16()? as usize;
+                        chunk.take(2 + 2 + 2)?;
+                        let opacity = chunk.u8()?;
+
+                        group_visible.truncate(level);
+                        let visible = flags & 1 != 0 && group_visible.iter().all(|v| *v);
+                        if layer_type == 1 {
+                            group_visible.push(visible);
+                        }
+                        file.layers.push(Layer {
+                            visible,
+                            opacity: if layer_opacity_valid { opacity } else { 255 },
+                            is_image: layer_type == 0,
+                        });
+                    }
+                    0x2005 => cels.push(file.parse_cel(&mut chunk)?),
+                    0x2018 => {
+                        let count = chunk.u16()?;
+                        chunk.take(8)?;
+                        for _ in 0..count {
+                            let from = chunk.u16()? as usize;
+                            let to = chunk.u16()? as usize;
+                            let direction = chunk.u8()?;
+                            chunk.take(2 + 6 + 3 + 1)?;
+                            let name = chunk.string()?;
+                            file.tags.push(Tag {
+                                from,
+                                to: to.max(from),
+                                direction,
+                                name,
+                            });
+                        }
+                    }
+                    0x2019 => {
+                        let len = chunk.u32()? as usize;
+                        let first = chunk.u32()? as usize;
+                        let last = chunk.u32()? as usize;
+                        chunk.take(8)?;
+                        // indexed pixels are single bytes, larger palettes can't be referenced
+                        let len = len.min(MAX_PALETTE_LEN);
+                        file.palette.resize(len.max(file.palette.len()), [0; 4]);
+                        for index in first..=last {
+                            let flags = chunk.u16()?;
+                            let color = chunk.take(4)?.try_into().unwrap();
+                            if let Some(entry) = file.palette.get_mut(index) {
+                                *entry = color;
+                            }
+                            if flags & 1 != 0 {
+                                chunk.string()?;
+                            }
+                        }
+                    }
+                    0x2022 => {
+                        let keys = chunk.u32()?;
+                        // flags and reserved
+                        chunk.take(4 + 4)?;
+                        let name = chunk.string()?;
+                        // only the first key, slices don't move between frames in an atlas
+                        if keys > 0 {
+                            chunk.u32()?;
+                            let position = IVec2::new(chunk.i32()?, chunk.i32()?);
+                            let size = UVec2::new(chunk.u32()?, chunk.u32()?);
+                            file.slices.push(Slice {
+                                name,
+                                position: position.max(IVec2::ZERO).as_uvec2(),
+                                size,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            file.frames.push(Frame { duration, cels });
+        }
+
+        if file.tags.iter().any(|tag| tag.to >= file.frames.len()) {
+            return Err(AtlasError::Aseprite(
+                "tag references a missing frame".to_string(),
+            ));
+        }
+        Ok(file)
+    }
+
+    fn parse_cel(&self, chunk: &mut Bytes) -> Result<Cel, AtlasError> {
+        let layer = chunk.u16()? as usize;
+        let position = IVec2::new(chunk.i16()? as i32, chunk.i16()? as i32);
+        let opacity = chunk.u8()?;
+        let cel_type = chunk.u16()?;
+        let z_index = chunk.i16()?;
+        chunk.take(5)?;
+
+        let content = match cel_type {
+            0 | 2 => {
+                let size = UVec2::new(chunk.u16()? as u32, chunk.u16()? as u32);
+                let raw = if cel_type == 0 {
+                    chunk.data.to_vec()
+                } else {
+                    let mut raw = Vec::new();
+                    ZlibDecoder::new(chunk.data).read_to_end(&mut raw)?;
+                    raw
+                };
+                CelContent::Pixels {
+                    size,
+                    data: self.to_rgba(&raw, size)?,
+                }
+            }
+            1 => CelContent::Linked(chunk.u16()? as usize),
+            _ => CelContent::Unsupported,
+        };
+        Ok(Cel {
+            layer,
+            position,
+            opacity,
+            z_index,
+            content,
+        })
+    }
+
+    fn to_rgba(&self, raw: &[u8], size: UVec2) -> Result<Vec<u8>, AtlasError> {
+        let pixels = size.element_product() as usize;
+        let bytes_per_pixel = (self.color_depth / 8) as usize;
+        if raw.len() < pixels * bytes_per_pixel {
+            return Err(AtlasError::Aseprite("cel is missing pixels".to_string()));
+        }
+        let rgba = match self.color_depth {
+            32 => raw[..pixels * 4].to_vec(),
+            16 => raw
+                .chunks_exact(2)
+                .take(pixels)
+                .flat_map(|px| [px[0], px[0], px[0], px[1]])
+                .collect(),
+            8 => raw
+                .iter()
+                .take(pixels)
+                .flat_map(|&index| match index == self.transparent_index {
+                    true => [0; 4],
+                    false => self.palette.get(index as usize).copied().unwrap_or([0; 4]),
+                })
+                .collect(),
+            depth => {
+                return Err(AtlasError::Aseprite(format!(
+                    "unsupported color depth {depth}"
+                )));
+            }
+        };
+        Ok(rgba)
+    }
+
+    /// Flattens the visible layers of `frame` onto a canvas sized RGBA buffer
+    fn flatten(&self, frame: usize) -> Vec<u8> {
+        let mut canvas = vec![0; self.size.element_product() as usize * 4];
+
+        let mut cels = self.frames[frame]
+            .cels
+            .iter()
+            .filter(|cel| {
+                self.layers
+                    .get(cel.layer)
+                    .is_some_and(|layer| layer.visible && layer.is_image)
+            })
+            .collect::<Vec<_>>();
+        // the z-index moves a cel in front of or behind other layers
+        cels.sort_by_key(|cel| (cel.layer as i32 + cel.z_index as i32, cel.z_index));
+
+        for cel in cels {
+            let content = match &cel.content {
+                CelContent::Linked(linked) => self
+                    .frames
+                    .get(*linked)
+                    .and_then(|frame| frame.cels.iter().find(|c| c.layer == cel.layer))
+                    .map(|c| &c.content),
+                content => Some(content),
+            };
+            let Some(CelContent::Pixels { size, data }) = content else {
+                continue;
+            };
+            let opacity =
+                cel.opacity as f32 / 255.0 * self.layers[cel.layer].opacity as f32 / 255.0;
+            for y in 0..size.y as i32 {
+                for x in 0..size.x as i32 {
+                    let target = cel.position + IVec2::new(x, y);
+                    if target.cmplt(IVec2::ZERO).any() || target.cmpge(self.size.as_ivec2()).any() {
+                        continue;
+                    }
+                    let src = (y as usize * size.x as usize + x as usize) * 4;
+                    let dst = (target.y as usize * self.size.x as usize + target.x as usize) * 4;
+                    blend_over(&mut canvas[dst..dst + 4], &data[src..src + 4], opacity);
+                }
+            }
+        }
+        canvas
+    }
+
+    /// Lays out the flattened frames of every row next to each other
+    fn render_sheet(&self, rows: &[Vec<usize>], cells: UVec2) -> Image {
+        let sheet_size = cells * self.size;
+        let mut sheet = vec![0; sheet_size.element_product() as usize * 4];
+        let flattened = (0..self.frames.len())
+            .map(|frame| self.flatten(frame))
+            .collect::<Vec<_>>();
+
+        let row_bytes = self.size.x as usize * 4;
+        for (row, frames) in rows.iter().enumerate() {
+            for (column, &frame) in frames.iter().enumerate() {
+                let origin = UVec2::new(column as u32, row as u32) * self.size;
+                for y in 0..self.size.y as usize {
+                    let dst =
+                        ((origin.y as usize + y) * sheet_size.x as usize + origin.x as usize) * 4;
+                    sheet[dst..dst + row_bytes]
+                        .copy_from_slice(&flattened[frame][y * row_bytes..(y + 1) * row_bytes]);
+                }
+            }
+        }
+
+        Image::new(
+            Extent3d {
+                width: sheet_size.x,
+                height: sheet_size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            sheet,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        )
+    }
+}
+
+/// Straight alpha "normal" blending of `src` with `opacity` over `dst`
+fn blend_over(dst: &mut [u8], src: &[u8], opacity: f32) {
+    let src_a = src[3] as f32 / 255.0 * opacity;
+    if src_a <= 0.0 {
+        return;
+    }
+    let dst_a = dst[3] as f32 / 255.0;
+    let out_a = src_a + dst_a * (1.0 - src_a);
+    for c in 0..3 {
+        let color = (src[c] as f32 * src_a + dst[c] as f32 * dst_a * (1.0 - src_a)) / out_a;
+        dst[c] = color.round() as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+
+    use super::*;
+
+    /// Minimal file of `size` with one frame per entry of `frames`, each a list of chunks
+    fn file(size: UVec2, color_depth: u16, frames: &[Vec<Vec<u8>>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(0u32.to_le_bytes());
+        data.extend(0xA5E0u16.to_le_bytes());
+        data.extend((frames.len() as u16).to_le_bytes());
+        data.extend((size.x as u16).to_le_bytes());
+        data.extend((size.y as u16).to_le_bytes());
+        data.extend(color_depth.to_le_bytes());
+        // layer opacity is valid
+        data.extend(1u32.to_le_bytes());
+        data.resize(128, 0);
+        for chunks in frames {
+            let body = chunks.concat();
+            data.extend((16 + body.len() as u32).to_le_bytes());
+            data.extend(0xF1FAu16.to_le_bytes());
+            data.extend((chunks.len() as u16).to_le_bytes());
+            data.extend(100u16.to_le_bytes());
+            data.extend([0; 2]);
+            data.extend((chunks.len() as u32).to_le_bytes());
+            data.extend(body);
+        }
+        data
+    }
+
+    fn chunk(chunk_type: u16, body: &[u8]) -> Vec<u8> {
+        [
+            &(6 + body.len() as u32).to_le_bytes()[..],
+            &chunk_type.to_le_bytes(),
+            body,
+        ]
+        .concat()
+    }
+
+    fn layer() -> Vec<u8> {
+        // visible image layer at the top level, opaque
+        chunk(0x2004, &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255])
+    }
+
+    fn cel(cel_type: u16, size: UVec2, pixels: &[u8]) -> Vec<u8> {
+        let mut body = vec![0; 2 + 2 + 2];
+        body.push(255);
+        body.extend(cel_type.to_le_bytes());
+        body.extend([0; 2 + 5]);
+        body.extend((size.x as u16).to_le_bytes());
+        body.extend((size.y as u16).to_le_bytes());
+        body.extend(pixels);
+        chunk(0x2005, &body)
+    }
+
+    fn tag(from: u16, to: u16, direction: u8) -> Tag {
+        Tag {
+            from: from as usize,
+            to: to as usize,
+            direction,
+            name: "tag".to_string(),
+        }
+    }
+
+    #[test]
+    fn ping_pong_tags_play_inner_frames_twice() {
+        assert_eq!(tag(0, 3, 2).playback_order(), [0, 1, 2, 3, 2, 1]);
+        assert_eq!(tag(0, 3, 3).playback_order(), [3, 2, 1, 0, 1, 2]);
+        assert_eq!(tag(1, 2, 1).playback_order(), [2, 1]);
+    }
+
+    #[test]
+    fn short_ping_pong_tags_have_no_inner_frames() {
+        assert_eq!(tag(4, 4, 2).playback_order(), [4]);
+        assert_eq!(tag(4, 4, 3).playback_order(), [4]);
+        assert_eq!(tag(4, 5, 2).playback_order(), [4, 5]);
+        assert_eq!(tag(4, 5, 3).playback_order(), [5, 4]);
+    }
+
+    #[test]
+    fn tags_are_read_from_their_chunk() {
+        let mut body = 1u16.to_le_bytes().to_vec();
+        body.extend([0; 8]);
+        body.extend([0, 0, 0, 0, 2]);
+        body.extend([0; 2 + 6 + 3 + 1]);
+        body.extend(4u16.to_le_bytes());
+        body.extend(b"walk");
+        let data = file(UVec2::ONE, 32, &[vec![chunk(0x2018, &body)]]);
+
+        let file = AsepriteFile::parse(&data).unwrap();
+        assert_eq!(file.tags.len(), 1);
+        assert_eq!(file.tags[0].name, "walk");
+        assert_eq!(file.tags[0].playback_order(), [0]);
+    }
+
+    #[test]
+    fn oversized_palettes_are_capped() {
+        let mut body = Vec::new();
+        body.extend(100_000u32.to_le_bytes());
+        body.extend(0u32.to_le_bytes());
+        body.extend(1u32.to_le_bytes());
+        body.extend([0; 8]);
+        for color in [[1, 2, 3, 255], [4, 5, 6, 255]] {
+            body.extend(0u16.to_le_bytes());
+            body.extend(color);
+        }
+        let indexed = cel(0, UVec2::new(2, 1), &[0, 1]);
+        let data = file(
+            UVec2::new(2, 1),
+            8,
+            &[vec![chunk(0x2019, &body), layer(), indexed]],
+        );
+
+        let file = AsepriteFile::parse(&data).unwrap();
+        assert_eq!(file.palette.len(), MAX_PALETTE_LEN);
+        assert_eq!(file.palette[1], [4, 5, 6, 255]);
+        // index 0 is the transparent one
+        assert_eq!(file.flatten(0), [0, 0, 0, 0, 4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn compressed_cels_are_inflated() {
+        let pixels = [10, 20, 30, 255, 40, 50, 60, 128];
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&pixels).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let data = file(
+            UVec2::new(2, 1),
+            32,
+            &[vec![layer(), cel(2, UVec2::new(2, 1), &compressed)]],
+        );
+
+        let file = AsepriteFile::parse(&data).unwrap();
+        assert_eq!(file.flatten(0), pixels);
+    }
+
+    #[test]
+    fn unknown_chunks_are_skipped() {
+        let data = file(
+            UVec2::ONE,
+            32,
+            &[vec![
+                chunk(0x2007, &[1, 2, 3]),
+                layer(),
+                cel(0, UVec2::ONE, &[1, 2, 3, 255]),
+            ]],
+        );
+
+        let file = AsepriteFile::parse(&data).unwrap();
+        assert_eq!(file.layers.len(), 1);
+        assert_eq!(file.flatten(0), [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let data = file(
+            UVec2::new(2, 1),
+            32,
+            &[vec![layer(), cel(0, UVec2::new(2, 1), &[0; 8])]],
+        );
+        assert!(AsepriteFile::parse(&data).is_ok());
+        for len in [0, 100, 128, 140, data.len() - 20, data.len() - 1] {
+            assert!(AsepriteFile::parse(&data[..len]).is_err(), "{len} bytes");
+        }
+    }
+
+    #[test]
+    fn chunks_longer_than_their_frame_are_errors() {
+        let mut layer = layer();
+        layer[..4].copy_from_slice(&1000u32.to_le_bytes());
+        let data = file(UVec2::ONE, 32, &[vec![layer]]);
+        assert!(AsepriteFile::parse(&data).is_err());
+    }
+
+    #[test]
+    fn cels_missing_pixels_are_errors() {
+        let data = file(
+            UVec2::new(2, 2),
+            32,
+            &[vec![layer(), cel(0, UVec2::new(2, 2), &[0; 12])]],
+        );
+        assert!(AsepriteFile::parse(&data).is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+
+use crate::definition::*;
+use crate::picking::HitMasks;
+
+/// Sheet cut into tiles, loaded from a `*.meta.yml` definition by the [`AtlasLoader`](crate::AtlasLoader)
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct Atlas {
+    pub tile_size: UVec2,
+    /// Border repeated around every layer, see [`StackingOptions::extrude`](crate::StackingOptions::extrude)
+    pub extrude: u32,
+    /// Size of the sheet in tiles
+    pub grid: UVec2,
+    /// Array texture with one layer per tile, row by row
+    #[dependency]
+    pub image: Handle<Image>,
+    /// Color swaps of all entries, see [`palette_table`](crate::palette_table)
+    #[dependency]
+    pub palettes: Handle<Image>,
+    /// Companion sheets stacked like `image`, see [`AtlasDefinition::normal_map`]
+    #[dependency]
+    pub normal_map: Option<Handle<Image>>,
+    /// See [`AtlasDefinition::emissive_map`]
+    #[dependency]
+    pub emissive_map: Option<Handle<Image>>,
+    /// Opaque pixels of every layer of `image`, used by the [`AtlasPickingPlugin`](crate::AtlasPickingPlugin)
+    #[dependency]
+    pub hit_masks: Handle<HitMasks>,
+    /// Also loadable as labeled sub assets: `belts.meta.yml#belt`
+    pub entries: HashMap<Arc<str>, Handle<AtlasEntry>>,
+    /// Layers left behind by removed entries, reused by [`AtlasComposer::insert`](crate::AtlasComposer::insert)
+    pub free_layers: Vec<u32>,
+}
+
+impl Atlas {
+    pub fn layer(&self, cell: UVec2) -> u32 {
+        cell_to_layer(self.grid, cell)
+    }
+}
+
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct AtlasEntry {
+    pub id: Arc<str>,
+    /// Same texture as [`Atlas::image`]
+    #[dependency]
+    pub image: Handle<Image>,
+    /// Same texture as [`Atlas::palettes`]
+    #[dependency]
+    pub palettes: Handle<Image>,
+    /// Row of each of its swaps in `palettes`
+    pub palette_rows: HashMap<Arc<str>, u32>,
+    /// Same textures as [`Atlas::normal_map`] and [`Atlas::emissive_map`]
+    #[dependency]
+    pub normal_map: Option<Handle<Image>>,
+    #[dependency]
+    pub emissive_map: Option<Handle<Image>>,
+    /// Same as [`Atlas::hit_masks`]
+    #[dependency]
+    pub hit_masks: Handle<HitMasks>,
+    pub tile_size: UVec2,
+    /// Same as [`Atlas::extrude`]
+    pub extrude: u32,
+    /// Size of the sheet in tiles
+    pub grid: UVec2,
+    /// Layers of entries inserted at runtime, which don't follow the sheet
+    pub layer_table: Option<LayerTable>,
+    pub definition: AtlasEntryDefinition,
+}
+
+/// Layer of every cell of an entry's own small sheet, row by row
+#[derive(Debug, Clone)]
+pub struct LayerTable {
+    /// Cells per row
+    pub width: u32,
+    pub layers: Vec<u32>,
+}
+
+impl AtlasEntry {
+    /// Layer of the top left tile shown in `state`
+    pub fn layer(&self, state: &AtlasEntryState) -> u32 {
+        self.cell_layer(self.definition.tile(state))
+    }
+
+    /// Layers of all tiles shown in `state`, with their cell relative to the top left one
+    pub fn layers(&self, state: &AtlasEntryState) -> impl Iterator<Item = (UVec2, u32)> {
+        let origin = self.definition.tile(state);
+        let size = self.definition.drawn_size(state);
+        (0..size.y)
+            .flat_map(move |y| (0..size.x).map(move |x| UVec2::new(x, y)))
+            .map(move |cell| (cell, self.cell_layer(origin + cell)))
+    }
+
+    /// Row of the swap named `palette` in [`Self::palettes`], 0 (no swap) for unknown names
+    pub fn palette_row(&self, palette: Option<&str>) -> u32 {
+        palette
+            .and_then(|palette| self.palette_rows.get(palette))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Size in tiles of the sheet [`Self::definition`] is relative to, the entry's own one for inserted entries
+    pub fn sheet_grid(&self) -> UVec2 {
+        match &self.layer_table {
+            Some(table) => UVec2::new(table.width, table.layers.len() as u32 / table.width.max(1)),
+            None => self.grid,
+        }
+    }
+
+    pub(crate) fn cell_layer(&self, cell: UVec2) -> u32 {
+        match &self.layer_table {
+            Some(table) => table.layers[(cell.y * table.width + cell.x) as usize],
+            None => cell_to_layer(self.grid, cell),
+        }
+    }
+
+    /// Cells covered on the grid in `state`, see [`AtlasEntryDefinition::footprint`]
+    pub fn footprint(&self, state: &AtlasEntryState) -> UVec2 {
+        self.definition.footprint(state.rotation)
+    }
+}
+
+fn cell_to_layer(grid: UVec2, cell: UVec2) -> u32 {
+    cell.y * grid.x + cell.x
+}
@@ -0,0 +1,114 @@
+#ifdef ATLAS_3D
+#import bevy_pbr::mesh_functions
+#else
+#import bevy_sprite::mesh2d_functions as mesh_functions
+#endif
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(0) var atlas_texture: texture_2d_array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(1) var atlas_sampler: sampler;
+@group(#{MATERIAL_BIND_GROUP}) @binding(2) var palette_texture: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(3) var normal_texture: texture_2d_array<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(4) var emissive_texture: texture_2d_array<f32>;
+
+struct AtlasLight {
+    direction: vec3<f32>,
+    color: vec4<f32>,
+    ambient: vec4<f32>,
+};
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(5) var<uniform> light: AtlasLight;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) uv: vec2<f32>,
+    @location(2) color: vec4<f32>,
+    @location(3) layer: u32,
+    @location(4) palette: u32,
+    @location(5) flip: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) @interpolate(flat) layer: u32,
+    @location(3) @interpolate(flat) palette: u32,
+    @location(4) @interpolate(flat) flip: u32,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+#ifdef ATLAS_3D
+    out.position = mesh_functions::mesh_position_local_to_clip(world_from_local, vec4<f32>(vertex.position, 1.0));
+#else
+    out.position = mesh_functions::mesh2d_position_local_to_clip(world_from_local, vec4<f32>(vertex.position, 1.0));
+#endif
+    out.uv = vertex.uv;
+    out.color = vertex.color;
+    out.layer = vertex.layer;
+    out.palette = vertex.palette;
+    out.flip = vertex.flip;
+    return out;
+}
+
+// 8 bit sRGB value of a linear color, the precision the sheet and the palette table are stored with.
+// Matching in linear space would need a tolerance, which lets dark shades collide.
+fn srgb_bytes(color: vec4<f32>) -> vec4<u32> {
+    let linear = clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    let srgb = select(
+        1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055,
+        linear * 12.92,
+        linear <= vec3<f32>(0.0031308),
+    );
+    return vec4<u32>(round(vec4<f32>(srgb, saturate(color.a)) * 255.0));
+}
+
+// Rows of the palette table alternate source and replacement colors, a transparent source ends the row
+fn swap_palette(color: vec4<f32>, row: u32) -> vec4<f32> {
+    if (row == 0u) {
+        return color;
+    }
+    let bytes = srgb_bytes(color);
+    let width = textureDimensions(palette_texture).x;
+    for (var i = 0u; i + 1u < width; i += 2u) {
+        let source = textureLoad(palette_texture, vec2<u32>(i, row), 0);
+        if (source.a == 0.0) {
+            break;
+        }
+        if (all(srgb_bytes(source) == bytes)) {
+            return textureLoad(palette_texture, vec2<u32>(i + 1u, row), 0);
+        }
+    }
+    return color;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let sampled = textureSample(atlas_texture, atlas_sampler, in.uv, in.layer);
+    var color = swap_palette(sampled, in.palette) * in.color;
+#ifdef ATLAS_NORMAL_MAP
+    var normal = normalize(textureSample(normal_texture, atlas_sampler, in.uv, in.layer).xyz * 2.0 - 1.0);
+    // mirrored sprites face the light with their mirrored side
+    if ((in.flip & 1u) != 0u) {
+        normal.x = -normal.x;
+    }
+    if ((in.flip & 2u) != 0u) {
+        normal.y = -normal.y;
+    }
+    let diffuse = max(dot(normal, normalize(light.direction)), 0.0);
+    color = vec4<f32>(color.rgb * (light.ambient.rgb + light.color.rgb * diffuse), color.a);
+#endif
+#ifdef ATLAS_EMISSIVE_MAP
+    let emissive = textureSample(emissive_texture, atlas_sampler, in.uv, in.layer);
+    color = vec4<f32>(color.rgb + emissive.rgb * emissive.a, color.a);
+#endif
+#ifdef ATLAS_3D
+    if (color.a < 0.5) {
+        discard;
+    }
+#endif
+    return color;
+}
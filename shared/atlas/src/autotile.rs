@@ -0,0 +1,162 @@
+//! Neighbor dependent variants of sprites on a grid, see [`AutotileDefinition`]
+
+use bevy::platform::collections::HashMap;
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+
+use crate::*;
+
+pub(crate) struct AtlasAutotilePlugin;
+
+impl Plugin for AtlasAutotilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, update_autotiles.before(update_atlas_batches));
+    }
+}
+
+/// Index of the sprites placed on this grid with [`AutotileCell`]
+#[derive(Component, Debug, Default)]
+pub struct AutotileGrid {
+    cells: HashMap<IVec2, (Entity, AssetId<AtlasEntry>)>,
+    positions: HashMap<Entity, IVec2>,
+}
+
+impl AutotileGrid {
+    pub fn get(&self, cell: IVec2) -> Option<Entity> {
+        self.cells.get(&cell).map(|(entity, _)| *entity)
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = (IVec2, Entity)> {
+        self.cells
+            .iter()
+            .map(|(cell, (entity, _))| (*cell, *entity))
+    }
+
+    /// Stops tracking `entity`, returns the cell it occupied
+    fn remove(&mut self, entity: Entity) -> Option<IVec2> {
+        let cell = self.positions.remove(&entity)?;
+        if self.get(cell) == Some(entity) {
+            self.cells.remove(&cell);
+        }
+        Some(cell)
+    }
+
+    fn insert(&mut self, entity: Entity, cell: IVec2, entry: AssetId<AtlasEntry>) {
+        if let Some((replaced, _)) = self.cells.insert(cell, (entity, entry))
+            && replaced != entity
+        {
+            self.positions.remove(&replaced);
+        }
+        self.positions.insert(entity, cell);
+    }
+}
+
+/// Places the [`AtlasSprite`] on this entity on an [`AutotileGrid`], one sprite per cell.
+/// Entries with an `autotile` rule get their variant from their neighbors, which are updated as well
+/// whenever a cell is placed, moved, removed or changes its entry.
+/// Moving the sprite in the world is still up to its `Transform`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutotileCell {
+    pub grid: Entity,
+    pub cell: IVec2,
+}
+
+fn update_autotiles(
+    mut tiles: Query<(Entity, Ref<AutotileCell>, &mut AtlasSprite)>,
+    mut removed: RemovedComponents<AutotileCell>,
+    mut entry_events: MessageReader<AssetEvent<AtlasEntry>>,
+    entries: Res<Assets<AtlasEntry>>,
+    mut grids: Query<(Entity, &mut AutotileGrid)>,
+) {
+    let mut dirty = HashMap::<Entity, HashSet<IVec2>>::new();
+    let mut mark = |grid: Entity, cell: IVec2| {
+        let cells = dirty.entry(grid).or_default();
+        cells.insert(cell);
+        cells.extend(
+            AutotileMode::Blob
+                .neighbors()
+                .iter()
+                .map(|offset| cell + offset),
+        );
+    };
+
+    for entity in removed.read() {
+        for (grid, mut cells) in &mut grids {
+            if let Some(cell) = cells.remove(entity) {
+                mark(grid, cell);
+            }
+        }
+    }
+
+    let loaded = entry_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for (entity, tile, sprite) in &tiles {
+        let entry = sprite.entry.id();
+        if tile.is_changed() {
+            // the cell may have moved to another grid as well
+            for (grid, mut cells) in &mut grids {
+                if grid != tile.grid
+                    && let Some(cell) = cells.remove(entity)
+                {
+                    mark(grid, cell);
+                }
+            }
+        }
+        let Ok((_, mut cells)) = grids.get_mut(tile.grid) else {
+            continue;
+        };
+        let placed = cells.positions.get(&entity).copied();
+        let placed_entry = placed
+            .and_then(|cell| cells.cells.get(&cell))
+            .map(|(_, id)| *id);
+        if placed != Some(tile.cell) || placed_entry != Some(entry) {
+            if let Some(cell) = cells.remove(entity) {
+                mark(tile.grid, cell);
+            }
+            cells.insert(entity, tile.cell, entry);
+            mark(tile.grid, tile.cell);
+        } else if loaded.contains(&entry) {
+            mark(tile.grid, tile.cell);
+        }
+    }
+
+    for (grid, dirty) in dirty {
+        let Ok((_, cells)) = grids.get(grid) else {
+            continue;
+        };
+        for cell in dirty {
+            let Some((entity, entry)) = cells.cells.get(&cell) else {
+                continue;
+            };
+            let Some(entry) = entries.get(*entry) else {
+                continue;
+            };
+            let Some(autotile) = &entry.definition.autotile else {
+                continue;
+            };
+            let mask = autotile.mask(|offset| {
+                cells
+                    .cells
+                    .get(&(cell + offset))
+                    .and_then(|(_, neighbor)| entries.get(*neighbor))
+                    .is_some_and(|neighbor| {
+                        neighbor.image == entry.image
+                            && (neighbor.id == entry.id
+                                || autotile.connects_to.iter().any(|id| **id == *neighbor.id))
+                    })
+            });
+            let variant = autotile.variant(mask);
+            if let Ok((_, _, mut sprite)) = tiles.get_mut(*entity)
+                && sprite.state.variant != variant
+            {
+                sprite.state.variant = variant;
+            }
+        }
+    }
+}
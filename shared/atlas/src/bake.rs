@@ -0,0 +1,263 @@
+//! Writing stacked textures to KTX2 files, used by the `bake_atlas` binary
+
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureDataOrder;
+use bevy::render::render_resource::TextureFormat;
+
+use crate::AtlasError;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+/// Channel ids of the RGBSDA color model
+const RED: u8 = 0;
+const GREEN: u8 = 1;
+const BLUE: u8 = 2;
+const ALPHA: u8 = 15;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Unorm,
+    Srgb,
+    Float,
+}
+
+/// Vulkan format, channel order and bits per channel of the formats that can be baked
+fn ktx2_format(format: TextureFormat) -> Option<(u32, &'static [u8], u32, Encoding)> {
+    const RGBA: &[u8] = &[RED, GREEN, BLUE, ALPHA];
+    const BGRA: &[u8] = &[BLUE, GREEN, RED, ALPHA];
+    Some(match format {
+        TextureFormat::R8Unorm => (9, &[RED], 8, Encoding::Unorm),
+        TextureFormat::Rg8Unorm => (16, &[RED, GREEN], 8, Encoding::Unorm),
+        TextureFormat::Rgba8Unorm => (37, RGBA, 8, Encoding::Unorm),
+        TextureFormat::Rgba8UnormSrgb => (43, RGBA, 8, Encoding::Srgb),
+        TextureFormat::Bgra8Unorm => (44, BGRA, 8, Encoding::Unorm),
+        TextureFormat::Bgra8UnormSrgb => (50, BGRA, 8, Encoding::Srgb),
+        TextureFormat::Rgba16Unorm => (91, RGBA, 16, Encoding::Unorm),
+        TextureFormat::Rgba16Float => (97, RGBA, 16, Encoding::Float),
+        TextureFormat::Rgba32Float => (109, RGBA, 32, Encoding::Float),
+        _ => return None,
+    })
+}
+
+/// Encodes a stacked array texture with all its layers and mip levels as KTX2, without supercompression.
+/// Only uncompressed 8, 16 and 32 bit per channel formats are supported.
+pub fn stacked_to_ktx2(stacked: &Image) -> Result<Vec<u8>, AtlasError> {
+    let descriptor = &stacked.texture_descriptor;
+    let (vk_format, channels, bits, encoding) =
+        ktx2_format(descriptor.format).ok_or(AtlasError::UnsupportedFormat(descriptor.format))?;
+    let data = stacked.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+
+    let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+    let layers = descriptor.size.depth_or_array_layers as usize;
+    let levels = descriptor.mip_level_count as usize;
+    let texel_bytes = channels.len() * bits as usize / 8;
+    let level_bytes = (0..levels)
+        .map(|level| {
+            (size >> level as u32).max(UVec2::ONE).element_product() as usize * texel_bytes
+        })
+        .collect::<Vec<_>>();
+    let layer_bytes = level_bytes.iter().sum::<usize>();
+
+    let dfd = data_format_descriptor(channels, bits, encoding, texel_bytes);
+    let header_bytes = KTX2_IDENTIFIER.len() + 9 * 4 + 4 * 4 + 2 * 8;
+    let dfd_offset = header_bytes + levels * 3 * 8;
+
+    // levels follow the descriptor from the smallest to the largest, each aligned to whole texels
+    let alignment = lcm(texel_bytes, 4);
+    let mut offset = dfd_offset + dfd.len();
+    let mut level_offsets = vec![0; levels];
+    for level in (0..levels).rev() {
+        offset = offset.next_multiple_of(alignment);
+        level_offsets[level] = offset;
+        offset += level_bytes[level] * layers;
+    }
+
+    let mut out = Vec::with_capacity(offset);
+    out.extend_from_slice(&KTX2_IDENTIFIER);
+    for value in [
+        vk_format,
+        bits / 8,
+        size.x,
+        size.y,
+        0,
+        layers as u32,
+        1,
+        levels as u32,
+        0,
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0,
+        0,
+    ] {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    // no supercompression global data
+    out.extend_from_slice(&[0; 16]);
+    for level in 0..levels {
+        let length = (level_bytes[level] * layers) as u64;
+        for value in [level_offsets[level] as u64, length, length] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    out.extend_from_slice(&dfd);
+    for level in (0..levels).rev() {
+        out.resize(level_offsets[level], 0);
+        let before = level_bytes[..level].iter().sum::<usize>();
+        // KTX2 stores all layers of a level together
+        for layer in 0..layers {
+            let start = match stacked.data_order {
+                TextureDataOrder::LayerMajor => layer * layer_bytes + before,
+                TextureDataOrder::MipMajor => before * layers + layer * level_bytes[level],
+            };
+            out.extend_from_slice(&data[start..][..level_bytes[level]]);
+        }
+    }
+    Ok(out)
+}
+
+/// Basic data format descriptor block describing one sample per channel
+fn data_format_descriptor(
+    channels: &[u8],
+    bits: u32,
+    encoding: Encoding,
+    texel_bytes: usize,
+) -> Vec<u8> {
+    let block_size = 24 + 16 * channels.len() as u32;
+    let mut dfd = Vec::with_capacity(4 + block_size as usize);
+    dfd.extend_from_slice(&(4 + block_size).to_le_bytes());
+    // Khronos vendor, basic descriptor type, version 2
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&(2 | block_size << 16).to_le_bytes());
+    let transfer = match encoding {
+        Encoding::Srgb => 2,
+        _ => 1,
+    };
+    // RGBSDA color model, BT.709 primaries, straight alpha, 1x1 texel blocks
+    dfd.extend_from_slice(&[1, 1, transfer, 0, 0, 0, 0, 0]);
+    dfd.extend_from_slice(&[texel_bytes as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for (i, channel) in channels.iter().enumerate() {
+        let (qualifiers, lower, upper) = match encoding {
+            // signed float
+            Encoding::Float => (0xC0, (-1.0f32).to_bits(), 1.0f32.to_bits()),
+            // alpha is never sRGB encoded
+            Encoding::Srgb if *channel == ALPHA => (0x10, 0, u32::MAX >> (32 - bits)),
+            _ => (0, 0, u32::MAX >> (32 - bits)),
+        };
+        dfd.extend_from_slice(&(i as u16 * bits as u16).to_le_bytes());
+        dfd.extend_from_slice(&[(bits - 1) as u8, channel | qualifiers, 0, 0, 0, 0]);
+        dfd.extend_from_slice(&lower.to_le_bytes());
+        dfd.extend_from_slice(&upper.to_le_bytes());
+    }
+    dfd
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    let gcd = |mut a: usize, mut b: usize| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::RenderAssetUsages;
+    use bevy::image::CompressedImageFormats;
+    use bevy::image::ktx2_buffer_to_image;
+    use bevy::render::render_resource::Extent3d;
+    use bevy::render::render_resource::TextureDimension;
+
+    use super::*;
+    use crate::StackingOptions;
+    use crate::stack_sheet;
+
+    fn sheet(size: UVec2, format: TextureFormat) -> Image {
+        let bytes = size.element_product() * format.block_copy_size(None).unwrap();
+        Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            (0..bytes).map(|i| (i * 7) as u8).collect(),
+            format,
+            RenderAssetUsages::default(),
+        )
+    }
+
+    /// Bytes of `stacked` in the level by level order of KTX2 files
+    fn mip_major(stacked: &Image) -> Vec<u8> {
+        let descriptor = &stacked.texture_descriptor;
+        let texel = descriptor.format.block_copy_size(None).unwrap() as usize;
+        let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+        let levels = (0..descriptor.mip_level_count)
+            .map(|mip| (size >> mip).max(UVec2::ONE).element_product() as usize * texel)
+            .collect::<Vec<_>>();
+        let layer_bytes = levels.iter().sum::<usize>();
+        let data = stacked.data.as_ref().unwrap();
+        let mut out = Vec::with_capacity(data.len());
+        for (level, bytes) in levels.iter().enumerate() {
+            let before = levels[..level].iter().sum::<usize>();
+            for layer in 0..descriptor.size.depth_or_array_layers as usize {
+                out.extend_from_slice(&data[layer * layer_bytes + before..][..*bytes]);
+            }
+        }
+        out
+    }
+
+    fn round_trip(stacked: &Image, srgb: bool) {
+        let baked = stacked_to_ktx2(stacked).unwrap();
+        let loaded = ktx2_buffer_to_image(&baked, CompressedImageFormats::NONE, srgb).unwrap();
+
+        let (expected, actual) = (&stacked.texture_descriptor, &loaded.texture_descriptor);
+        assert_eq!(actual.size, expected.size);
+        assert_eq!(actual.mip_level_count, expected.mip_level_count);
+        assert_eq!(actual.format, expected.format);
+        assert_eq!(loaded.data_order, TextureDataOrder::MipMajor);
+        assert_eq!(loaded.data, Some(mip_major(stacked)));
+        // baking the mip major copy gives the same file
+        assert_eq!(stacked_to_ktx2(&loaded).unwrap(), baked);
+    }
+
+    #[test]
+    fn mipmapped_atlases_load_back_unchanged() {
+        let options = StackingOptions {
+            extrude: 1,
+            mipmaps: true,
+            sampling: None,
+        };
+        let sheet = sheet(UVec2::new(12, 8), TextureFormat::Rgba8UnormSrgb);
+        let (stacked, _) = stack_sheet(&sheet, UVec2::splat(4), &options).unwrap();
+        assert_eq!(stacked.texture_descriptor.size.depth_or_array_layers, 6);
+        assert_eq!(stacked.texture_descriptor.mip_level_count, 3);
+        round_trip(&stacked, true);
+    }
+
+    #[test]
+    fn wide_formats_load_back_unchanged() {
+        for format in [
+            TextureFormat::Rgba8Unorm,
+            TextureFormat::Rgba16Unorm,
+            TextureFormat::Rgba32Float,
+        ] {
+            let sheet = sheet(UVec2::new(6, 3), format);
+            let (stacked, _) = stack_sheet(&sheet, UVec2::splat(3), &default()).unwrap();
+            round_trip(&stacked, false);
+        }
+    }
+
+    #[test]
+    fn compressed_atlases_are_not_baked() {
+        let mut stacked = sheet(UVec2::splat(4), TextureFormat::Rgba8Unorm);
+        stacked.texture_descriptor.format = TextureFormat::Bc1RgbaUnorm;
+        assert!(matches!(
+            stacked_to_ktx2(&stacked),
+            Err(AtlasError::UnsupportedFormat(TextureFormat::Bc1RgbaUnorm))
+        ));
+    }
+}
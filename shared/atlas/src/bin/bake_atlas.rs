@@ -0,0 +1,214 @@
+//! Checks and bakes `*.meta.yml` atlas definitions without starting bevy, e.g. in a build pipeline.
+//! Runs the same validation, packing and stacking as the `AtlasLoader` and, with `--out`,
+//! writes the stacked textures as KTX2 next to a resolved definition the loader takes as it is.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use atlas::*;
+use bevy::asset::RenderAssetUsages;
+use bevy::image::CompressedImageFormats;
+use bevy::image::ImageSampler;
+use bevy::image::ImageType;
+use bevy::prelude::*;
+
+const USAGE: &str = "\
+usage: bake_atlas [--out <dir>] <path>...
+
+Validates every *.meta.yml in the given files and folders (searched recursively).
+With --out, also writes the stacked sheet and companions as KTX2 and the resolved
+definition into <dir>, keeping the layout below each given folder.";
+
+fn main() -> ExitCode {
+    let mut out = None;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out" => match args.next() {
+                Some(dir) => out = Some(PathBuf::from(dir)),
+                None => return usage("--out needs a folder"),
+            },
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            flag if flag.starts_with('-') => return usage(&format!("unknown option {flag}")),
+            path => paths.push(PathBuf::from(path)),
+        }
+    }
+    if paths.is_empty() {
+        return usage("no definitions given");
+    }
+
+    let mut failed = 0;
+    let mut definitions = Vec::new();
+    for path in &paths {
+        let root = match path.is_dir() {
+            true => path.as_path(),
+            false => path.parent().unwrap_or(Path::new("")),
+        };
+        if let Err(err) = find_definitions(path, root, &mut definitions) {
+            eprintln!("{}: {err}", path.display());
+            failed += 1;
+        }
+    }
+
+    for (path, relative) in &definitions {
+        let out = out.as_ref().map(|out| out.join(relative));
+        match bake(path, out.as_deref()) {
+            Ok(summary) => println!("{}: {summary}", path.display()),
+            Err(err) => {
+                eprintln!("{}: {err}", path.display());
+                failed += 1;
+            }
+        }
+    }
+    println!("{} definitions, {failed} failed", definitions.len());
+    match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+fn usage(problem: &str) -> ExitCode {
+    eprintln!("{problem}\n\n{USAGE}");
+    ExitCode::from(2)
+}
+
+/// Collects the definitions at or below `path` with their path relative to `root`
+fn find_definitions(
+    path: &Path,
+    root: &Path,
+    definitions: &mut Vec<(PathBuf, PathBuf)>,
+) -> std::io::Result<()> {
+    if path.is_dir() {
+        let mut children = fs::read_dir(path)?
+            .map(|child| Ok(child?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        children.sort();
+        for child in children {
+            find_definitions(&child, root, definitions)?;
+        }
+    } else if path.to_string_lossy().ends_with(".meta.yml") {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        definitions.push((path.to_path_buf(), relative.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Validates and stacks the definition at `path`, writing the results to `out` if given
+fn bake(path: &Path, out: Option<&Path>) -> Result<String, Box<dyn Error>> {
+    let definition: AtlasDefinition = serde_yaml::from_slice(&fs::read(path)?)?;
+    if definition.grid.is_some() {
+        return Err("the definition is baked already".into());
+    }
+    let folder = path.parent().unwrap_or(Path::new(""));
+    let tile_size = definition.tile_size;
+
+    let (sheet, packed) = match &definition.pack {
+        Some(_) if definition.normal_map.is_some() || definition.emissive_map.is_some() => {
+            return Err(AtlasError::CompanionWithPack.into());
+        }
+        Some(pack) => {
+            let mut images = BTreeMap::new();
+            for file in fs::read_dir(folder.join(&pack.folder))? {
+                let file = file?.path();
+                if file.extension().is_none_or(|ext| ext != "png") {
+                    continue;
+                }
+                let Some(id) = file.file_stem().and_then(|stem| stem.to_str()) else {
+                    continue;
+                };
+                images.insert(id.to_string(), read_image(&file, true)?);
+            }
+            pack_images(images, pack, tile_size)?
+        }
+        None => {
+            let sheet = definition
+                .image
+                .clone()
+                .unwrap_or_else(|| sibling_sheet(path));
+            (read_image(&folder.join(sheet), true)?, default())
+        }
+    };
+    let companions = CompanionSheets {
+        // normals are vectors, not colors
+        normal_map: definition
+            .normal_map
+            .as_ref()
+            .map(|file| read_image(&folder.join(file), false))
+            .transpose()?,
+        emissive_map: definition
+            .emissive_map
+            .as_ref()
+            .map(|file| read_image(&folder.join(file), true))
+            .transpose()?,
+    };
+
+    let stacked = stack_atlas(&sheet, tile_size, &definition.stacking, companions)?;
+    let entries = merge_packed(packed, definition.entries);
+    validate_entries(&entries, stacked.grid)?;
+    let summary = format!(
+        "{} entries on {} layers of {}",
+        entries.len(),
+        stacked.grid.element_product(),
+        tile_size + 2 * definition.stacking.extrude,
+    );
+    let Some(out) = out else {
+        return Ok(summary);
+    };
+
+    let file_name = out
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let stem = file_name.strip_suffix(".meta.yml").unwrap_or(file_name);
+    let out_folder = out.parent().unwrap_or(Path::new(""));
+    fs::create_dir_all(out_folder)?;
+    let write = |suffix: &str, image: Option<&Image>| -> Result<_, Box<dyn Error>> {
+        let Some(image) = image else {
+            return Ok(None);
+        };
+        let name = format!("{stem}{suffix}.ktx2");
+        fs::write(out_folder.join(&name), stacked_to_ktx2(image)?)?;
+        Ok(Some(name))
+    };
+
+    let resolved = AtlasDefinition {
+        tile_size,
+        image: write("", Some(&stacked.image))?,
+        pack: None,
+        normal_map: write(".normal_map", stacked.companions.normal_map.as_ref())?,
+        emissive_map: write(".emissive_map", stacked.companions.emissive_map.as_ref())?,
+        entries,
+        stacking: definition.stacking,
+        grid: Some(stacked.grid),
+    };
+    fs::write(out, serde_yaml::to_string(&resolved)?)?;
+    Ok(format!("{summary}, written to {}", out.display()))
+}
+
+/// Decodes an image file by its extension, like the `ImageLoader` with default settings would
+fn read_image(path: &Path, is_srgb: bool) -> Result<Image, Box<dyn Error>> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    let bytes = fs::read(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let image = Image::from_buffer(
+        &bytes,
+        ImageType::Extension(extension),
+        CompressedImageFormats::all(),
+        is_srgb,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    )
+    .map_err(|err| format!("{}: {err}", path.display()))?;
+    Ok(image)
+}
@@ -0,0 +1,303 @@
+//! Adding and removing entries of atlases at runtime, see [`AtlasComposer`]
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::TextureDataOrder;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+
+use crate::*;
+
+/// Edits atlases in place, e.g. to add generated icons or player made emblems.
+/// Inserted tiles take the layers of removed entries first and grow the stacked texture otherwise.
+///
+/// Reloading a loaded atlas from disk drops the entries inserted into it.
+#[derive(SystemParam)]
+pub struct AtlasComposer<'w> {
+    atlases: ResMut<'w, Assets<Atlas>>,
+    entries: ResMut<'w, Assets<AtlasEntry>>,
+    images: ResMut<'w, Assets<Image>>,
+    hit_masks: ResMut<'w, Assets<HitMasks>>,
+}
+
+impl AtlasComposer<'_> {
+    /// Atlas without a sheet, filled through [`Self::insert`]
+    pub fn create(
+        &mut self,
+        tile_size: UVec2,
+        options: &StackingOptions,
+    ) -> Result<Handle<Atlas>, AtlasError> {
+        // array textures can't be empty, so the atlas starts with one free layer
+        let blank = Image::new_fill(
+            Extent3d {
+                width: tile_size.x,
+                height: tile_size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        let (stacked, _) = stack_sheet(&blank, tile_size, options)?;
+        let (palettes, _) = palette_table(&BTreeMap::new());
+        let hit_masks = HitMasks::new(&stacked, options.extrude);
+
+        Ok(self.atlases.add(Atlas {
+            tile_size,
+            extrude: options.extrude,
+            grid: UVec2::ZERO,
+            image: self.images.add(stacked),
+            palettes: self.images.add(palettes),
+            normal_map: None,
+            emissive_map: None,
+            hit_masks: self.hit_masks.add(hit_masks),
+            entries: default(),
+            free_layers: vec![0],
+        }))
+    }
+
+    /// Cuts `image` into tiles and adds them as entry `id`, replacing an entry of the same id.
+    /// `definition` is relative to `image`, as if it were the sheet. Its palettes are ignored,
+    /// companion maps get flat tiles.
+    pub fn insert(
+        &mut self,
+        atlas: impl Into<AssetId<Atlas>>,
+        id: impl Into<Arc<str>>,
+        image: &Image,
+        definition: AtlasEntryDefinition,
+    ) -> Result<Handle<AtlasEntry>, AtlasError> {
+        let atlas_id = atlas.into();
+        let id = id.into();
+        let atlas = self
+            .atlases
+            .get(atlas_id)
+            .ok_or(AtlasError::AtlasNotLoaded)?;
+        let stacked = self
+            .images
+            .get(&atlas.image)
+            .ok_or(AtlasError::AtlasNotLoaded)?;
+
+        let format = stacked.texture_descriptor.format;
+        let converted;
+        let image = match image.texture_descriptor.format == format {
+            true => image,
+            false => {
+                converted = image.convert(format).ok_or(AtlasError::UnsupportedFormat(
+                    image.texture_descriptor.format,
+                ))?;
+                &converted
+            }
+        };
+        let options = StackingOptions {
+            extrude: atlas.extrude,
+            mipmaps: stacked.texture_descriptor.mip_level_count > 1,
+            sampling: None,
+        };
+        let (tiles, grid) = stack_sheet(image, atlas.tile_size, &options)?;
+        validate_entry(id.to_string(), &definition, grid)?;
+        validate_connections(&id, &definition, |target| {
+            *target == *id || atlas.entries.contains_key(target)
+        })?;
+        let tiles = tiles.data.ok_or(AtlasError::NoCpuData)?;
+        let tile_bytes = tiles.len() / grid.element_product() as usize;
+
+        // everything that can fail is checked read only, before the atlas is touched
+        check_layer_size(stacked, tile_bytes)?;
+        let companion_texel = |companion: Option<&Handle<Image>>, color: Color| {
+            companion
+                .and_then(|h| self.images.get(h))
+                .map(|companion| {
+                    mip_major_levels(companion)?;
+                    layer_bytes(companion)?;
+                    flat_texel(companion, color)
+                })
+                .transpose()
+        };
+        let normal_texel = companion_texel(atlas.normal_map.as_ref(), NORMAL_FLAT)?;
+        let emissive_texel = companion_texel(atlas.emissive_map.as_ref(), Color::NONE)?;
+        mip_major_levels(stacked)?;
+
+        self.remove(atlas_id, &id);
+        let atlas = self.atlases.get_mut(atlas_id).unwrap();
+        let stacked = self
+            .images
+            .get_mut(&atlas.image)
+            .ok_or(AtlasError::AtlasNotLoaded)?;
+        make_layer_major(stacked)?;
+        let layers = tiles
+            .chunks_exact(tile_bytes)
+            .map(|tile| write_layer(stacked, atlas.free_layers.pop(), tile))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(hit_masks) = self.hit_masks.get_mut(&atlas.hit_masks) {
+            for layer in &layers {
+                hit_masks.update_layer(stacked, *layer);
+            }
+        }
+
+        // companions keep the same layer indices
+        let companions = [
+            (atlas.normal_map.as_ref(), normal_texel),
+            (atlas.emissive_map.as_ref(), emissive_texel),
+        ];
+        for (companion, texel) in companions {
+            if let Some((companion, texel)) =
+                companion.and_then(|h| self.images.get_mut(h)).zip(texel)
+            {
+                make_layer_major(companion)?;
+                fill_layers(companion, &layers, &texel)?;
+            }
+        }
+
+        let entry = self.entries.add(AtlasEntry {
+            id: id.clone(),
+            image: atlas.image.clone(),
+            palettes: atlas.palettes.clone(),
+            palette_rows: default(),
+            normal_map: atlas.normal_map.clone(),
+            emissive_map: atlas.emissive_map.clone(),
+            hit_masks: atlas.hit_masks.clone(),
+            tile_size: atlas.tile_size,
+            extrude: atlas.extrude,
+            grid: atlas.grid,
+            layer_table: Some(LayerTable {
+                width: grid.x,
+                layers,
+            }),
+            definition,
+        });
+        atlas.entries.insert(id, entry.clone());
+        Ok(entry)
+    }
+
+    /// Removes entry `id` from the atlas. Layers of inserted entries become free,
+    /// the ones of loaded entries stay, as other entries may share them.
+    pub fn remove(&mut self, atlas: impl Into<AssetId<Atlas>>, id: &str) -> Option<AtlasEntry> {
+        let atlas = self.atlases.get_mut(atlas.into())?;
+        let handle = atlas.entries.remove(id)?;
+        let entry = self.entries.remove(&handle)?;
+        if let Some(table) = &entry.layer_table {
+            atlas.free_layers.extend(&table.layers);
+        }
+        Some(entry)
+    }
+}
+
+/// Texel of a flat surface in tangent space
+const NORMAL_FLAT: Color = Color::linear_rgba(0.5, 0.5, 1.0, 1.0);
+
+/// Reorders the data of baked atlases, which keep the level order of their KTX2 file,
+/// so every layer is followed by its own mip chain and can be written in one piece
+fn make_layer_major(image: &mut Image) -> Result<(), AtlasError> {
+    let Some(level_bytes) = mip_major_levels(image)? else {
+        return Ok(());
+    };
+    let layers = image.texture_descriptor.size.depth_or_array_layers as usize;
+    let data = image.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+    let mut reordered = Vec::with_capacity(data.len());
+    for layer in 0..layers {
+        let mut level_start = 0;
+        for bytes in &level_bytes {
+            reordered.extend_from_slice(&data[level_start + layer * bytes..][..*bytes]);
+            level_start += bytes * layers;
+        }
+    }
+    image.data = Some(reordered);
+    image.data_order = TextureDataOrder::LayerMajor;
+    Ok(())
+}
+
+/// Bytes of every mip level of one layer, `None` if `image` is layer major already.
+/// Reordering needs whole texels, so block-compressed formats are unsupported.
+fn mip_major_levels(image: &Image) -> Result<Option<Vec<usize>>, AtlasError> {
+    if image.data_order == TextureDataOrder::LayerMajor {
+        return Ok(None);
+    }
+    let descriptor = &image.texture_descriptor;
+    let bpp = texel_bytes(descriptor.format)?;
+    let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+    image.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+    Ok(Some(
+        (0..descriptor.mip_level_count)
+            .map(|mip| (size >> mip).max(UVec2::ONE).element_product() as usize * bpp)
+            .collect(),
+    ))
+}
+
+fn texel_bytes(format: TextureFormat) -> Result<usize, AtlasError> {
+    format
+        .block_copy_size(None)
+        .filter(|_| format.block_dimensions() == (1, 1))
+        .map(|bytes| bytes as usize)
+        .ok_or(AtlasError::UnsupportedFormat(format))
+}
+
+/// `color` as one texel in the format of `companion`
+fn flat_texel(companion: &Image, color: Color) -> Result<Vec<u8>, AtlasError> {
+    let format = companion.texture_descriptor.format;
+    let mut texel = Image::new_fill(
+        Extent3d::default(),
+        TextureDimension::D2,
+        &vec![0; texel_bytes(format)?],
+        format,
+        RenderAssetUsages::MAIN_WORLD,
+    );
+    texel
+        .set_color_at(0, 0, color)
+        .map_err(|_| AtlasError::UnsupportedFormat(format))?;
+    texel.data.ok_or(AtlasError::NoCpuData)
+}
+
+/// Bytes of one layer including its mip chain
+fn layer_bytes(stacked: &Image) -> Result<usize, AtlasError> {
+    let data = stacked.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+    Ok(data.len() / stacked.texture_descriptor.size.depth_or_array_layers as usize)
+}
+
+/// Tiles stacked with a different mip count or format than the atlas don't fit its layers
+fn check_layer_size(stacked: &Image, tile_bytes: usize) -> Result<usize, AtlasError> {
+    let layer = layer_bytes(stacked)?;
+    if tile_bytes != layer {
+        return Err(AtlasError::LayerSize {
+            tile: tile_bytes,
+            layer,
+        });
+    }
+    Ok(layer)
+}
+
+/// Overwrites `layer`, or appends a new one without it
+fn write_layer(stacked: &mut Image, layer: Option<u32>, tile: &[u8]) -> Result<u32, AtlasError> {
+    let len = check_layer_size(stacked, tile.len())?;
+    let size = &mut stacked.texture_descriptor.size;
+    let data = stacked.data.as_mut().ok_or(AtlasError::NoCpuData)?;
+    match layer {
+        Some(layer) => {
+            data[layer as usize * len..][..len].copy_from_slice(tile);
+            Ok(layer)
+        }
+        None => {
+            data.extend_from_slice(tile);
+            size.depth_or_array_layers += 1;
+            Ok(size.depth_or_array_layers - 1)
+        }
+    }
+}
+
+/// Sets `layers` of a companion map to `texel`, adding layers up to the highest one
+fn fill_layers(companion: &mut Image, layers: &[u32], texel: &[u8]) -> Result<(), AtlasError> {
+    let len = layer_bytes(companion)?;
+    let tile = texel.iter().copied().cycle().take(len).collect::<Vec<_>>();
+    for layer in layers {
+        while companion.texture_descriptor.size.depth_or_array_layers <= *layer {
+            write_layer(companion, None, &tile)?;
+        }
+        write_layer(companion, Some(*layer), &tile)?;
+    }
+    Ok(())
+}
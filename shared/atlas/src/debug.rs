@@ -0,0 +1,406 @@
+//! Egui browser for loaded atlases, see [`AtlasDebugPlugin`]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use bevy_egui::EguiPrimaryContextPass;
+use bevy_egui::EguiTextureHandle;
+use bevy_egui::egui;
+
+use crate::*;
+
+/// Window listing all loaded atlases and their entries. Previews every layer, plays clips with scrubbing and
+/// steps through rotations and variants. Entries reaching past their sheet and atlases that failed to load
+/// are shown in red, with the reason.
+///
+/// Expects the `EguiPlugin` to be added already, e.g. by the inspector of a game's debug plugin.
+pub struct AtlasDebugPlugin {
+    /// Opens and closes the window
+    pub toggle: KeyCode,
+}
+
+impl Default for AtlasDebugPlugin {
+    fn default() -> Self {
+        Self {
+            toggle: KeyCode::F4,
+        }
+    }
+}
+
+impl Plugin for AtlasDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(AtlasInspector {
+            toggle: self.toggle,
+            ..default()
+        })
+        .add_systems(Update, toggle_inspector)
+        .add_systems(
+            EguiPrimaryContextPass,
+            inspector_window.run_if(|inspector: Res<AtlasInspector>| inspector.open),
+        );
+    }
+}
+
+#[derive(Resource)]
+pub struct AtlasInspector {
+    pub open: bool,
+    toggle: KeyCode,
+    atlas: Option<AssetId<Atlas>>,
+    entry: Option<Arc<str>>,
+    state: AtlasEntryState,
+    playing: bool,
+    elapsed: Duration,
+    zoom: f32,
+    /// Layers copied into 2D images egui can show, with the id of the copy
+    previews: HashMap<(AssetId<Image>, u32), (AssetId<Image>, egui::TextureId)>,
+}
+
+impl Default for AtlasInspector {
+    fn default() -> Self {
+        Self {
+            open: false,
+            toggle: KeyCode::F4,
+            atlas: None,
+            entry: None,
+            state: default(),
+            playing: true,
+            elapsed: Duration::ZERO,
+            zoom: 2.0,
+            previews: default(),
+        }
+    }
+}
+
+fn toggle_inspector(input: Res<ButtonInput<KeyCode>>, mut inspector: ResMut<AtlasInspector>) {
+    if input.just_pressed(inspector.toggle) {
+        inspector.open = !inspector.open;
+    }
+}
+
+fn inspector_window(
+    mut contexts: EguiContexts,
+    mut inspector: ResMut<AtlasInspector>,
+    mut image_events: MessageReader<AssetEvent<Image>>,
+    time: Res<Time>,
+    asset_server: Res<AssetServer>,
+    failed: Res<FailedAtlases>,
+    atlases: Res<Assets<Atlas>>,
+    entries: Res<Assets<AtlasEntry>>,
+    mut images: ResMut<Assets<Image>>,
+) -> Result {
+    let inspector = &mut *inspector;
+
+    // reloaded atlases replace their stacked image in place
+    for event in image_events.read() {
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event {
+            inspector.previews.retain(|(image, _), (preview, _)| {
+                let keep = image != id;
+                if !keep {
+                    contexts.remove_image(*preview);
+                    images.remove(*preview);
+                }
+                keep
+            });
+        }
+    }
+
+    // egui only knows textures added before the window is drawn, so the selection shows up a frame later
+    if let Some(atlas) = inspector.atlas.and_then(|id| atlases.get(id)) {
+        let image = atlas.image.id();
+        for layer in 0..layer_count(atlas, &images) {
+            if inspector.previews.contains_key(&(image, layer)) {
+                continue;
+            }
+            let Some(preview) = images
+                .get(image)
+                .and_then(|stacked| stacked_layer(stacked, layer))
+            else {
+                continue;
+            };
+            let preview = images.add(preview);
+            let texture = contexts.add_image(EguiTextureHandle::Strong(preview.clone()));
+            inspector
+                .previews
+                .insert((image, layer), (preview.id(), texture));
+        }
+    }
+
+    let entry = inspector
+        .atlas
+        .and_then(|id| atlases.get(id))
+        .zip(inspector.entry.as_ref())
+        .and_then(|(atlas, entry)| atlas.entries.get(entry))
+        .and_then(|handle| entries.get(handle));
+    if let Some(clip) =
+        entry.and_then(|entry| entry.definition.clip(inspector.state.clip.as_deref()))
+        && inspector.playing
+    {
+        inspector.elapsed += time.delta();
+        inspector.state.animation = clip.frames_played(inspector.elapsed) % clip.frame_count();
+    }
+
+    let mut open = inspector.open;
+    egui::Window::new("Atlases")
+        .open(&mut open)
+        .default_width(420.0)
+        .show(contexts.ctx_mut()?, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let mut failed = failed.iter().collect::<Vec<_>>();
+                failed.sort_by_key(|(path, _)| path.to_string());
+                for (path, error) in failed {
+                    ui.colored_label(egui::Color32::RED, format!("{path}: {error}"));
+                }
+                atlas_list(ui, inspector, &asset_server, &atlases, &entries, &images);
+                if let Some(entry) = entry {
+                    ui.separator();
+                    entry_details(ui, inspector, entry);
+                }
+                if let Some(atlas) = inspector.atlas.and_then(|id| atlases.get(id)) {
+                    ui.separator();
+                    let layers = layer_count(atlas, &images);
+                    layer_grid(ui, inspector, atlas, &entries, layers, entry);
+                }
+            });
+        });
+    inspector.open = open;
+    Ok(())
+}
+
+fn atlas_list(
+    ui: &mut egui::Ui,
+    inspector: &mut AtlasInspector,
+    asset_server: &AssetServer,
+    atlases: &Assets<Atlas>,
+    entries: &Assets<AtlasEntry>,
+    images: &Assets<Image>,
+) {
+    let mut sorted = atlases
+        .iter()
+        .map(|(id, atlas)| {
+            let name = asset_server
+                .get_path(id)
+                .map_or_else(|| format!("{id}"), |path| path.to_string());
+            (name, id, atlas)
+        })
+        .collect::<Vec<_>>();
+    sorted.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+    for (name, id, atlas) in sorted {
+        egui::CollapsingHeader::new(format!(
+            "{name} ({} entries, {} layers)",
+            atlas.entries.len(),
+            layer_count(atlas, images)
+        ))
+        .id_salt(id)
+        .show(ui, |ui| {
+            let mut names = atlas.entries.keys().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                let selected =
+                    inspector.atlas == Some(id) && inspector.entry.as_ref() == Some(name);
+                let bounds = entries
+                    .get(&atlas.entries[name])
+                    .map(|entry| (entry.definition.extent(), entry.sheet_grid()));
+                let text = match bounds {
+                    Some((extent, grid)) if extent.cmpgt(grid).any() => {
+                        egui::RichText::new(format!("{name}: reaches {extent}, sheet is {grid}"))
+                            .color(egui::Color32::RED)
+                    }
+                    _ => egui::RichText::new(name.as_ref()),
+                };
+                if ui.selectable_label(selected, text).clicked() {
+                    inspector.atlas = Some(id);
+                    inspector.entry = Some(name.clone());
+                    inspector.state = default();
+                    inspector.elapsed = Duration::ZERO;
+                }
+            }
+        });
+    }
+}
+
+fn entry_details(ui: &mut egui::Ui, inspector: &mut AtlasInspector, entry: &AtlasEntry) {
+    let definition = &entry.definition;
+    ui.heading(entry.id.as_ref());
+    ui.label(format!(
+        "size {}, offset {}, reaches {} of {}",
+        definition.size,
+        definition.offset,
+        definition.extent(),
+        entry.sheet_grid()
+    ));
+
+    let clip_name = |clip: Option<&str>| clip.unwrap_or("default").to_string();
+    let clips = definition
+        .animation
+        .iter()
+        .map(|_| None)
+        .chain(
+            definition
+                .animations
+                .keys()
+                .map(|name| Some(Arc::from(name.as_str()))),
+        )
+        .collect::<Vec<Option<Arc<str>>>>();
+    if !clips.is_empty() {
+        egui::ComboBox::from_label("clip")
+            .selected_text(clip_name(inspector.state.clip.as_deref()))
+            .show_ui(ui, |ui| {
+                for clip in clips {
+                    let text = clip_name(clip.as_deref());
+                    if ui
+                        .selectable_value(&mut inspector.state.clip, clip, text)
+                        .changed()
+                    {
+                        inspector.elapsed = Duration::ZERO;
+                    }
+                }
+            });
+    }
+    if let Some(clip) = definition.clip(inspector.state.clip.as_deref()) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut inspector.playing, "play");
+            let frames = clip.frame_count().saturating_sub(1);
+            let scrub =
+                ui.add(egui::Slider::new(&mut inspector.state.animation, 0..=frames).text("frame"));
+            if scrub.changed() {
+                inspector.playing = false;
+            }
+            ui.label(format!(
+                "{:?}",
+                clip.frame_duration(inspector.state.animation)
+            ));
+        });
+    }
+    if let Some(rotation) = &definition.rotation {
+        let turns = match rotation.grid_rotations() {
+            GridRotations::PerAxis => 1,
+            GridRotations::All => 3,
+        };
+        ui.add(egui::Slider::new(&mut inspector.state.rotation, 0..=turns).text("rotation"));
+    }
+    if let Some(variants) = &definition.variants {
+        let last = variants.count().saturating_sub(1);
+        ui.add(egui::Slider::new(&mut inspector.state.variant, 0..=last).text("variant"));
+    }
+    ui.add(egui::Slider::new(&mut inspector.zoom, 1.0..=8.0).text("zoom"));
+
+    let size = entry.definition.drawn_size(&inspector.state);
+    let tile = egui::vec2(entry.tile_size.x as f32, entry.tile_size.y as f32) * inspector.zoom;
+    let layers = entry.layers(&inspector.state).collect::<Vec<_>>();
+    ui.scope(|ui| {
+        ui.spacing_mut().item_spacing = egui::Vec2::ZERO;
+        for row in layers.chunks(size.x as usize) {
+            ui.horizontal(|ui| {
+                for (_, layer) in row {
+                    match inspector.previews.get(&(entry.image.id(), *layer)) {
+                        Some((_, texture)) => {
+                            ui.add(tile_image(*texture, tile, entry.tile_size, entry.extrude));
+                        }
+                        None => {
+                            ui.allocate_space(tile);
+                        }
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// Crops the extruded border off a layer
+fn tile_image(
+    texture: egui::TextureId,
+    size: egui::Vec2,
+    tile_size: UVec2,
+    extrude: u32,
+) -> egui::Image<'static> {
+    let inset = extrude as f32 / (tile_size.as_vec2() + 2.0 * extrude as f32);
+    egui::Image::new(egui::load::SizedTexture::new(texture, size)).uv(egui::Rect::from_min_max(
+        egui::pos2(inset.x, inset.y),
+        egui::pos2(1.0 - inset.x, 1.0 - inset.y),
+    ))
+}
+
+/// Includes the layers added at runtime after the ones of the sheet
+fn layer_count(atlas: &Atlas, images: &Assets<Image>) -> u32 {
+    images.get(&atlas.image).map_or(0, |stacked| {
+        stacked.texture_descriptor.size.depth_or_array_layers
+    })
+}
+
+fn layer_grid(
+    ui: &mut egui::Ui,
+    inspector: &AtlasInspector,
+    atlas: &Atlas,
+    entries: &Assets<AtlasEntry>,
+    layers: u32,
+    entry: Option<&AtlasEntry>,
+) {
+    let shown = entry
+        .map(|entry| {
+            entry
+                .layers(&inspector.state)
+                .map(|(_, layer)| layer)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let size = egui::vec2(atlas.tile_size.x as f32, atlas.tile_size.y as f32);
+    // atlases composed at runtime have no sheet to follow
+    let columns = match atlas.grid.x {
+        0 => 8,
+        columns => columns,
+    };
+    // inserted entries follow their own sheet
+    let inserted = atlas
+        .entries
+        .values()
+        .filter_map(|handle| entries.get(handle))
+        .filter_map(|entry| Some((entry, entry.layer_table.as_ref()?)))
+        .flat_map(|(entry, table)| {
+            table.layers.iter().enumerate().map(move |(i, layer)| {
+                let cell = UVec2::new(i as u32 % table.width, i as u32 / table.width);
+                (*layer, (entry.id.clone(), cell))
+            })
+        })
+        .collect::<HashMap<_, _>>();
+    egui::CollapsingHeader::new(format!("layers ({layers}, sheet {})", atlas.grid)).show(
+        ui,
+        |ui| {
+            egui::Grid::new("atlas layers")
+                .spacing([2.0, 2.0])
+                .show(ui, |ui| {
+                    for layer in 0..layers {
+                        let response = match inspector.previews.get(&(atlas.image.id(), layer)) {
+                            Some((_, texture)) => {
+                                ui.add(tile_image(*texture, size, atlas.tile_size, atlas.extrude))
+                            }
+                            None => ui.label("?"),
+                        };
+                        let response = match inserted.get(&layer) {
+                            Some((id, cell)) => response
+                                .on_hover_text(format!("layer {layer}, cell {cell} of {id}")),
+                            None if atlas.free_layers.contains(&layer) => {
+                                response.on_hover_text(format!("layer {layer}, free"))
+                            }
+                            None if layer < atlas.grid.element_product() => {
+                                response.on_hover_text(format!(
+                                    "layer {layer}, cell {}",
+                                    UVec2::new(layer % columns, layer / columns)
+                                ))
+                            }
+                            None => response.on_hover_text(format!("layer {layer}, added")),
+                        };
+                        if shown.contains(&layer) {
+                            response.highlight();
+                        }
+                        if (layer + 1).is_multiple_of(columns) {
+                            ui.end_row();
+                        }
+                    }
+                });
+        },
+    );
+}
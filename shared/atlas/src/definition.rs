@@ -0,0 +1,804 @@
+//! Serde layout of the `*.meta.yml` atlas definitions
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use serde::de::Error as _;
+use serde_with::DeserializeAs;
+use serde_with::SerializeAs;
+use serde_with::serde_as;
+
+use crate::StackingOptions;
+
+pub trait GetFrameIndex {
+    type Param;
+    fn get_frame_index(&self, param: Self::Param) -> UVec2;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AtlasDefinition {
+    pub tile_size: UVec2,
+    /// Sheet path relative to the definition, defaults to the sibling png (`belts.meta.yml` -> `belts.png`)
+    #[serde(default)]
+    pub image: Option<String>,
+    /// Builds the sheet from loose images instead of `image`
+    #[serde(default)]
+    pub pack: Option<PackDefinition>,
+    /// Tangent space normal map laid out like the sheet, lights the sprites with [`AtlasLight`](crate::AtlasLight)
+    #[serde(default)]
+    pub normal_map: Option<String>,
+    /// Glow laid out like the sheet, added on top of the lit color and scaled by its alpha
+    #[serde(default)]
+    pub emissive_map: Option<String>,
+    /// With `pack`, an entry named like an image replaces the generated one and its offset is relative to the image
+    #[serde(alias = "objects", default)]
+    pub entries: BTreeMap<String, AtlasEntryDefinition>,
+    /// `extrude`, `mipmaps` and `sampling` next to the other keys
+    #[serde(flatten)]
+    pub stacking: StackingOptions,
+    /// Set by `bake_atlas`: `image` and the companions are already stacked from a sheet of this many tiles,
+    /// extruded and mipmapped, so only `sampling` still applies
+    #[serde(default)]
+    pub grid: Option<UVec2>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackDefinition {
+    /// Folder relative to the definition, every png in it becomes an entry named after its file stem.
+    /// Files added to it are only picked up when the definition itself reloads.
+    pub folder: String,
+    /// Images that are horizontal strips of equally sized animation frames, by entry id
+    #[serde(default)]
+    pub strips: BTreeMap<String, StripDefinition>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StripDefinition {
+    pub count: u32,
+    #[serde_as(as = "FrameTime")]
+    pub frame_duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameSequence {
+    pub stride: UVec2,
+    pub count: u32,
+}
+
+impl GetFrameIndex for FrameSequence {
+    type Param = u32;
+    fn get_frame_index(&self, frame: u32) -> UVec2 {
+        let frame = frame % self.count;
+        UVec2::new(self.stride.x * frame, self.stride.y * frame)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AtlasEntryDefinition {
+    /// Size in tiles, also the footprint on the grid when not rotated
+    #[serde(default = "single_tile")]
+    pub size: UVec2,
+    /// Top left tile of the entry in the sheet
+    #[serde(default)]
+    pub offset: UVec2,
+    /// Clip played unless [`AtlasEntryState::clip`] names one of `animations`
+    #[serde(default)]
+    pub animation: Option<AnimationDefinition>,
+    /// Named clips, e.g. `idle`, `working` and `blocked` of a machine
+    #[serde(default)]
+    pub animations: BTreeMap<String, AnimationDefinition>,
+    #[serde(default)]
+    pub variants: Option<VariantsDefinition>,
+    #[serde(default)]
+    pub rotation: Option<RotationsDefinition>,
+    /// Picks the variant from the neighbors on an [`AutotileGrid`](crate::AutotileGrid)
+    #[serde(default)]
+    pub autotile: Option<AutotileDefinition>,
+    /// Recolors picked per sprite with [`AtlasSprite::palette`](crate::AtlasSprite::palette)
+    #[serde(default)]
+    pub palettes: Option<PalettesDefinition>,
+}
+
+fn single_tile() -> UVec2 {
+    UVec2::ONE
+}
+
+/// A single still tile
+impl Default for AtlasEntryDefinition {
+    fn default() -> Self {
+        Self {
+            size: single_tile(),
+            offset: UVec2::ZERO,
+            animation: None,
+            animations: BTreeMap::new(),
+            variants: None,
+            rotation: None,
+            autotile: None,
+            palettes: None,
+        }
+    }
+}
+
+impl AtlasEntryDefinition {
+    /// `None` selects the unnamed `animation`
+    pub fn clip(&self, name: Option<&str>) -> Option<&AnimationDefinition> {
+        match name {
+            Some(name) => self.animations.get(name),
+            None => self.animation.as_ref(),
+        }
+    }
+
+    /// All clips with their names, the unnamed `animation` is called `default`
+    pub fn clips(&self) -> impl Iterator<Item = (&str, &AnimationDefinition)> {
+        self.animation.iter().map(|clip| ("default", clip)).chain(
+            self.animations
+                .iter()
+                .map(|(name, clip)| (name.as_str(), clip)),
+        )
+    }
+
+    /// Top left tile of the entry shown in `state`
+    pub fn tile(&self, state: &AtlasEntryState) -> UVec2 {
+        let animation = self
+            .clip(state.clip.as_deref())
+            .map_or(UVec2::ZERO, |clip| clip.frame_offset(state.animation));
+        let variant = self.variants.as_ref().map_or(UVec2::ZERO, |variants| {
+            variants.get_frame_index(state.variant)
+        });
+        let rotation = self.rotation.as_ref().map_or(UVec2::ZERO, |rotation| {
+            rotation.get_frame_index(state.rotation)
+        });
+        self.offset + animation + variant + rotation
+    }
+
+    /// Cells covered on the grid when placed with `rotation` quarter turns
+    pub fn footprint(&self, rotation: u32) -> UVec2 {
+        match rotation % 2 {
+            0 => self.size,
+            _ => self.size.yx(),
+        }
+    }
+
+    /// Tiles drawn for `state`, starting at [`Self::tile`].
+    /// Pre-drawn rotations of non-square entries are stored with their footprint, so width and height swap.
+    pub fn drawn_size(&self, state: &AtlasEntryState) -> UVec2 {
+        match self.rotation {
+            Some(_) => self.footprint(state.rotation),
+            None => self.size,
+        }
+    }
+
+    /// Explains why the frames of this entry can't be cut from the sheet, if they can't.
+    /// Every frame sequence has to step past the tiles drawn for the previous frame.
+    pub fn validate_layout(&self) -> Result<(), String> {
+        if self.size.min_element() == 0 {
+            return Err(format!("size {} covers no tiles", self.size));
+        }
+        let drawn = match self.rotation {
+            Some(_) => self.size.max(self.size.yx()),
+            None => self.size,
+        };
+        let sequences = self
+            .clips()
+            .map(|(name, clip)| (format!("clip {name}"), &clip.seq))
+            .chain(
+                self.variants
+                    .iter()
+                    .map(|v| ("variants".to_string(), &v.seq)),
+            )
+            .chain(
+                self.rotation
+                    .iter()
+                    .map(|r| ("rotation".to_string(), &r.seq)),
+            );
+        for (name, seq) in sequences {
+            if seq.count == 0 {
+                return Err(format!("{name} needs a count of at least 1"));
+            }
+            if seq.count > 1 && seq.stride.cmplt(drawn).all() {
+                return Err(format!(
+                    "frames of {name} overlap, stride {} doesn't step past the drawn size {drawn}",
+                    seq.stride
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Tile right below and behind the furthest tile any state of this entry can reach
+    pub fn extent(&self) -> UVec2 {
+        let span = |seq: &FrameSequence| seq.stride * seq.count.saturating_sub(1);
+        let animation = self
+            .clips()
+            .map(|(_, clip)| clip.offset + span(&clip.seq))
+            .fold(UVec2::ZERO, UVec2::max);
+        let size = match self.rotation {
+            Some(_) => self.size.max(self.size.yx()),
+            None => self.size,
+        };
+        self.offset
+            + size
+            + animation
+            + self.variants.as_ref().map_or(UVec2::ZERO, |v| span(&v.seq))
+            + self.rotation.as_ref().map_or(UVec2::ZERO, |r| span(&r.seq))
+    }
+}
+
+/// Position of an entry along all axes it can vary in, resolved to its top left tile by
+/// [`AtlasEntryDefinition::tile`]. Axes the entry doesn't define are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AtlasEntryState {
+    /// Name of the clip in `animations`, `None` plays the unnamed `animation`
+    pub clip: Option<Arc<str>>,
+    /// Frame of the clip, usually set by an [`AtlasAnimator`](crate::AtlasAnimator)
+    pub animation: u32,
+    /// Quarter turns counter clockwise
+    pub rotation: u32,
+    pub variant: u32,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnimationDefinition {
+    /// First frame of the clip relative to the entry offset
+    #[serde(default)]
+    offset: UVec2,
+    #[serde_as(as = "FrameTime")]
+    #[serde(default)]
+    frame_duration: Duration,
+    /// Replaces `frame_duration` with one duration per frame
+    #[serde_as(as = "Option<Vec<FrameTime>>")]
+    #[serde(default)]
+    frame_durations: Option<Vec<Duration>>,
+    #[serde(flatten)]
+    seq: FrameSequence,
+}
+
+impl AnimationDefinition {
+    /// Frames of equal duration, `stride` tiles apart
+    pub fn uniform(frame_duration: Duration, stride: UVec2, count: u32) -> Self {
+        Self {
+            offset: UVec2::ZERO,
+            frame_duration,
+            frame_durations: None,
+            seq: FrameSequence { stride, count },
+        }
+    }
+
+    /// One frame per duration, `stride` tiles apart, starting at `offset` relative to the entry
+    pub fn per_frame(offset: UVec2, stride: UVec2, durations: Vec<Duration>) -> Self {
+        Self {
+            offset,
+            frame_duration: Duration::ZERO,
+            seq: FrameSequence {
+                stride,
+                count: durations.len() as u32,
+            },
+            frame_durations: Some(durations),
+        }
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.seq.count
+    }
+
+    /// How long `frame` is shown
+    pub fn frame_duration(&self, frame: u32) -> Duration {
+        match &self.frame_durations {
+            Some(durations) => durations[(frame % self.seq.count) as usize],
+            None => self.frame_duration,
+        }
+    }
+
+    /// Duration of one pass through all frames
+    pub fn cycle_duration(&self) -> Duration {
+        match &self.frame_durations {
+            Some(durations) => durations.iter().sum(),
+            None => self.frame_duration * self.seq.count,
+        }
+    }
+
+    /// Tile offset of `frame`, wrapping around after the last one
+    pub fn frame_offset(&self, frame: u32) -> UVec2 {
+        self.offset + self.seq.get_frame_index(frame)
+    }
+
+    /// Number of frames fully shown after `elapsed`, counting on past the last frame
+    pub fn frames_played(&self, elapsed: Duration) -> u32 {
+        let Some(durations) = &self.frame_durations else {
+            return (elapsed.as_secs_f32() / self.frame_duration.as_secs_f32()) as u32;
+        };
+        let cycle = self.cycle_duration();
+        if cycle.is_zero() {
+            return 0;
+        }
+        let cycles = (elapsed.as_secs_f64() / cycle.as_secs_f64()) as u32;
+        let mut rest = elapsed.saturating_sub(cycle * cycles);
+        let frames = durations
+            .iter()
+            .take_while(|duration| {
+                let shown = rest >= **duration;
+                rest = rest.saturating_sub(**duration);
+                shown
+            })
+            .count() as u32;
+        cycles * self.seq.count + frames
+    }
+
+    /// Explains why this clip can't be played, if it can't
+    pub fn validate(&self) -> Result<(), String> {
+        if self.seq.count == 0 {
+            return Err("count has to be at least 1".to_string());
+        }
+        match &self.frame_durations {
+            Some(durations) if durations.len() != self.seq.count as usize => Err(format!(
+                "expected {} frame_durations, found {}",
+                self.seq.count,
+                durations.len()
+            )),
+            Some(durations) if durations.iter().all(Duration::is_zero) => {
+                Err("frame_durations are all zero".to_string())
+            }
+            None if self.frame_duration.is_zero() => {
+                Err("needs a positive frame_duration or frame_durations".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl GetFrameIndex for AnimationDefinition {
+    type Param = Duration;
+    fn get_frame_index(&self, total_time: Self::Param) -> UVec2 {
+        self.frame_offset(self.frames_played(total_time))
+    }
+}
+
+/// Durations are given in seconds (`0.15`) or with a unit (`150ms`, `0.15s`)
+struct FrameTime;
+
+impl<'de> DeserializeAs<'de, Duration> for FrameTime {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(f64),
+            Text(String),
+        }
+
+        let seconds = match Raw::deserialize(deserializer)? {
+            Raw::Seconds(seconds) => seconds,
+            Raw::Text(text) => {
+                let text = text.trim();
+                match text.strip_suffix("ms") {
+                    Some(millis) => millis.trim().parse::<f64>().map(|ms| ms / 1000.0),
+                    None => text.strip_suffix('s').unwrap_or(text).trim().parse(),
+                }
+                .map_err(D::Error::custom)?
+            }
+        };
+        Duration::try_from_secs_f64(seconds).map_err(D::Error::custom)
+    }
+}
+
+impl SerializeAs<Duration> for FrameTime {
+    fn serialize_as<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariantsDefinition {
+    #[serde(flatten)]
+    seq: FrameSequence,
+}
+
+impl VariantsDefinition {
+    pub fn count(&self) -> u32 {
+        self.seq.count
+    }
+}
+
+impl GetFrameIndex for VariantsDefinition {
+    type Param = u32;
+    fn get_frame_index(&self, variant: Self::Param) -> UVec2 {
+        self.seq.get_frame_index(variant)
+    }
+}
+
+/// Named color swaps of an entry, e.g. team colors or a frozen look.
+/// Each swap lists one replacement per `source` color, in the same order.
+/// Colors are hex strings (`"#d04648"`, with optional alpha). Sheet pixels only match them exactly,
+/// so recolored entries should use nearest sampling and no mipmaps.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PalettesDefinition {
+    #[serde_as(as = "Vec<HexColor>")]
+    pub source: Vec<Srgba>,
+    #[serde_as(as = "BTreeMap<_, Vec<HexColor>>")]
+    pub swaps: BTreeMap<String, Vec<Srgba>>,
+}
+
+impl PalettesDefinition {
+    /// Names the swap that can't be applied and explains why, if there is one
+    pub fn validate(&self) -> Result<(), (String, String)> {
+        if let Some(color) = self.source.iter().find(|color| color.alpha == 0.0) {
+            return Err((
+                "source".to_string(),
+                format!("{} is fully transparent", color.to_hex()),
+            ));
+        }
+        match self
+            .swaps
+            .iter()
+            .find(|(_, colors)| colors.len() != self.source.len())
+        {
+            Some((name, colors)) => Err((
+                name.clone(),
+                format!(
+                    "expected {} colors like source, found {}",
+                    self.source.len(),
+                    colors.len()
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Colors are given as hex strings (`"#rrggbb"`, `"#rrggbbaa"` or the short forms)
+struct HexColor;
+
+impl<'de> DeserializeAs<'de, Srgba> for HexColor {
+    fn deserialize_as<D: Deserializer<'de>>(deserializer: D) -> Result<Srgba, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Srgba::hex(&text).map_err(D::Error::custom)
+    }
+}
+
+impl SerializeAs<Srgba> for HexColor {
+    fn serialize_as<S: Serializer>(color: &Srgba, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&color.to_hex())
+    }
+}
+
+/// Chooses the variant of an entry by which of its neighbors connect to it.
+/// Neighbors are numbered clockwise from north (+y): N, E, S, W for [`AutotileMode::Edges`],
+/// N, NE, E, SE, S, SW, W, NW for [`AutotileMode::Blob`], each connected one sets its bit in the mask.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutotileDefinition {
+    pub mode: AutotileMode,
+    /// Other entries of the same atlas that count as connected, the entry always connects to itself
+    #[serde(default)]
+    pub connects_to: Vec<String>,
+    /// Variant per mask, replacing the default order of the mode for the masks listed
+    #[serde(default)]
+    pub variants: BTreeMap<u8, u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutotileMode {
+    /// 4 bit mask of the edge neighbors, 16 variants in mask order (Wang tiles)
+    Edges,
+    /// 8 bit mask including corners, which only count when both edges next to them connect.
+    /// Leaves 47 distinct masks, their variants are in ascending mask order (blob tileset).
+    Blob,
+}
+
+impl AutotileMode {
+    /// Grid offsets of the neighbors, in mask bit order
+    pub fn neighbors(self) -> &'static [IVec2] {
+        const EDGES: [IVec2; 4] = [IVec2::Y, IVec2::X, IVec2::NEG_Y, IVec2::NEG_X];
+        const BLOB: [IVec2; 8] = [
+            IVec2::new(0, 1),
+            IVec2::new(1, 1),
+            IVec2::new(1, 0),
+            IVec2::new(1, -1),
+            IVec2::new(0, -1),
+            IVec2::new(-1, -1),
+            IVec2::new(-1, 0),
+            IVec2::new(-1, 1),
+        ];
+        match self {
+            AutotileMode::Edges => &EDGES,
+            AutotileMode::Blob => &BLOB,
+        }
+    }
+
+    /// Number of variants the default order needs
+    pub fn variant_count(self) -> u32 {
+        match self {
+            AutotileMode::Edges => 16,
+            AutotileMode::Blob => 47,
+        }
+    }
+
+    /// Clears the corners that don't matter, so equal looking tiles get the same mask
+    pub fn reduce(self, mask: u8) -> u8 {
+        match self {
+            AutotileMode::Edges => mask & 0b1111,
+            AutotileMode::Blob => (0..4).fold(mask, |mask, corner| {
+                let before = 1 << (corner * 2);
+                let after = 1 << ((corner * 2 + 2) % 8);
+                match mask & before != 0 && mask & after != 0 {
+                    true => mask,
+                    false => mask & !(1 << (corner * 2 + 1)),
+                }
+            }),
+        }
+    }
+}
+
+impl AutotileDefinition {
+    /// Mask of the neighbors for which `connected` returns true, already reduced
+    pub fn mask(&self, mut connected: impl FnMut(IVec2) -> bool) -> u8 {
+        let mask = self
+            .mode
+            .neighbors()
+            .iter()
+            .enumerate()
+            .filter(|(_, offset)| connected(**offset))
+            .fold(0, |mask, (bit, _)| mask | 1 << bit);
+        self.mode.reduce(mask)
+    }
+
+    /// Variant shown for a reduced `mask`
+    pub fn variant(&self, mask: u8) -> u32 {
+        if let Some(variant) = self.variants.get(&mask) {
+            return *variant;
+        }
+        match self.mode {
+            AutotileMode::Edges => mask as u32,
+            AutotileMode::Blob => (0..mask)
+                .filter(|lower| self.mode.reduce(*lower) == *lower)
+                .count() as u32,
+        }
+    }
+
+    /// Explains why the variants of the entry can't cover all masks, if they can't
+    pub fn validate(&self, variants: Option<&VariantsDefinition>) -> Result<(), String> {
+        let count = variants.map_or(1, |variants| variants.seq.count);
+        let masks = (0..=u8::MAX).filter(|mask| self.mode.reduce(*mask) == *mask);
+        match masks.map(|mask| self.variant(mask)).max() {
+            Some(needed) if needed >= count => {
+                Err(format!("needs {} variants, found {count}", needed + 1))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RotationsDefinition {
+    #[serde(flatten)]
+    seq: FrameSequence,
+}
+
+impl RotationsDefinition {
+    pub fn count(&self) -> u32 {
+        self.seq.count
+    }
+
+    pub fn grid_rotations(&self) -> GridRotations {
+        match self.seq.count {
+            ..4 => GridRotations::PerAxis,
+            _ => GridRotations::All,
+        }
+    }
+}
+
+impl GetFrameIndex for RotationsDefinition {
+    type Param = u32;
+    fn get_frame_index(&self, rotation: Self::Param) -> UVec2 {
+        let frame = match self.grid_rotations() {
+            GridRotations::PerAxis => rotation % 2,
+            GridRotations::All => rotation % 4,
+        };
+        self.seq.get_frame_index(frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridRotations {
+    /// Expects 2 frames: one for 0°+180° and one for 90°+270°
+    PerAxis,
+    /// Expects 4 frames: one for each 90° rotation
+    All,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn autotile(mode: AutotileMode, variants: &[(u8, u32)]) -> AutotileDefinition {
+        AutotileDefinition {
+            mode,
+            connects_to: Vec::new(),
+            variants: variants.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn corners_need_both_edges() {
+        // bits clockwise from north: N NE E SE S SW W NW
+        let cases = [
+            (0b0000_0010, 0b0000_0000),
+            (0b0000_0011, 0b0000_0001),
+            (0b0000_0110, 0b0000_0100),
+            (0b0000_0111, 0b0000_0111),
+            (0b0001_1100, 0b0001_1100),
+            (0b0111_0000, 0b0111_0000),
+            (0b1100_0001, 0b1100_0001),
+            (0b1000_0000, 0b0000_0000),
+            (0b1010_1010, 0b0000_0000),
+            (0b0101_0101, 0b0101_0101),
+            (0b1111_1110, 0b0111_1100),
+            (0b1111_1111, 0b1111_1111),
+        ];
+        for (mask, reduced) in cases {
+            assert_eq!(AutotileMode::Blob.reduce(mask), reduced, "mask {mask:08b}");
+        }
+    }
+
+    #[test]
+    fn edge_masks_drop_the_high_bits() {
+        for mask in [0b0000, 0b0101, 0b1111, 0b1111_0000, 0b1010_0110] {
+            assert_eq!(AutotileMode::Edges.reduce(mask), mask & 0b1111);
+        }
+    }
+
+    #[test]
+    fn blob_masks_leave_47_variants() {
+        let reduced = (0..=u8::MAX)
+            .filter(|mask| AutotileMode::Blob.reduce(*mask) == *mask)
+            .collect::<Vec<_>>();
+        assert_eq!(reduced.len() as u32, AutotileMode::Blob.variant_count());
+        for mask in 0..=u8::MAX {
+            assert!(reduced.contains(&AutotileMode::Blob.reduce(mask)));
+        }
+    }
+
+    #[test]
+    fn variants_follow_the_mask_order() {
+        let edges = autotile(AutotileMode::Edges, &[]);
+        for mask in 0..16 {
+            assert_eq!(edges.variant(mask), mask as u32);
+        }
+
+        let blob = autotile(AutotileMode::Blob, &[]);
+        let cases = [
+            (0b0000_0000, 0),
+            (0b0000_0001, 1),
+            (0b0000_0100, 2),
+            (0b0000_0101, 3),
+            (0b0000_0111, 4),
+            (0b0001_0000, 5),
+            (0b0111_1100, 31),
+            (0b1111_1111, 46),
+        ];
+        for (mask, variant) in cases {
+            assert_eq!(blob.variant(mask), variant, "mask {mask:08b}");
+        }
+    }
+
+    #[test]
+    fn listed_variants_replace_the_default_order() {
+        let blob = autotile(AutotileMode::Blob, &[(0b1111_1111, 0), (0, 3)]);
+        assert_eq!(blob.variant(0b1111_1111), 0);
+        assert_eq!(blob.variant(0), 3);
+        assert_eq!(blob.variant(0b0000_0001), 1);
+    }
+
+    #[test]
+    fn too_few_variants_are_rejected() {
+        let variants = |count| VariantsDefinition {
+            seq: FrameSequence {
+                stride: UVec2::X,
+                count,
+            },
+        };
+        let edges = autotile(AutotileMode::Edges, &[]);
+        assert!(edges.validate(Some(&variants(16))).is_ok());
+        assert!(edges.validate(Some(&variants(15))).is_err());
+        assert!(edges.validate(None).is_err());
+
+        let blob = autotile(AutotileMode::Blob, &[]);
+        assert!(blob.validate(Some(&variants(47))).is_ok());
+        assert!(blob.validate(Some(&variants(46))).is_err());
+        // all masks mapped to the first variant need only one
+        let single = (0..=u8::MAX).map(|mask| (mask, 0)).collect::<Vec<_>>();
+        assert!(autotile(AutotileMode::Blob, &single).validate(None).is_ok());
+    }
+
+    fn clip(yaml: &str) -> AnimationDefinition {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn frame_times_accept_fractions_and_units() {
+        let cases = [
+            ("0.15", 150),
+            ("2", 2000),
+            ("150ms", 150),
+            ("'12.5 ms'", 12),
+            ("0.25s", 250),
+            ("'1 s'", 1000),
+        ];
+        for (time, millis) in cases {
+            let clip = clip(&format!(
+                "{{ frame_duration: {time}, stride: [1, 0], count: 2 }}"
+            ));
+            assert_eq!(clip.frame_duration(0).as_millis(), millis, "{time}");
+        }
+        for time in ["-1", "fast", "10 min"] {
+            let yaml = format!("{{ frame_duration: {time}, stride: [1, 0], count: 2 }}");
+            assert!(
+                serde_yaml::from_str::<AnimationDefinition>(&yaml).is_err(),
+                "{time}"
+            );
+        }
+    }
+
+    #[test]
+    fn fractional_durations_count_whole_frames() {
+        let clip = clip("{ frame_duration: 0.15, stride: [1, 0], count: 4 }");
+        let cases = [(0, 0), (149, 0), (150, 1), (599, 3), (600, 4), (1000, 6)];
+        for (millis, played) in cases {
+            let elapsed = Duration::from_millis(millis);
+            assert_eq!(clip.frames_played(elapsed), played, "{millis}ms");
+        }
+        assert_eq!(clip.cycle_duration(), Duration::from_millis(600));
+    }
+
+    #[test]
+    fn per_frame_durations_count_on_over_cycles() {
+        let clip = clip("{ frame_durations: [100ms, 0.3, 50ms], stride: [0, 1], count: 3 }");
+        assert_eq!(clip.cycle_duration(), Duration::from_millis(450));
+        assert_eq!(clip.frame_duration(4), Duration::from_millis(300));
+        let cases = [
+            (0, 0),
+            (99, 0),
+            (100, 1),
+            (399, 1),
+            (400, 2),
+            (450, 3),
+            (550, 4),
+            (1349, 8),
+            (1350, 9),
+        ];
+        for (millis, played) in cases {
+            let elapsed = Duration::from_millis(millis);
+            assert_eq!(clip.frames_played(elapsed), played, "{millis}ms");
+        }
+        assert_eq!(
+            clip.get_frame_index(Duration::from_millis(550)),
+            UVec2::new(0, 1)
+        );
+    }
+
+    #[test]
+    fn clips_without_timing_are_rejected() {
+        let cases = [
+            "{ frame_duration: 0, stride: [1, 0], count: 2 }",
+            "{ frame_duration: 0.1, stride: [1, 0], count: 0 }",
+            "{ frame_durations: [0.1], stride: [1, 0], count: 2 }",
+            "{ frame_durations: [0, 0ms], stride: [1, 0], count: 2 }",
+        ];
+        for yaml in cases {
+            assert!(clip(yaml).validate().is_err(), "{yaml}");
+        }
+        assert!(
+            clip("{ frame_durations: [0, 1], stride: [1, 0], count: 2 }")
+                .validate()
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use bevy::asset::LoadDirectError;
+use bevy::asset::ParseAssetPathError;
+use bevy::asset::io::AssetReaderError;
+use bevy::asset::io::MissingAssetSourceError;
+use bevy::prelude::*;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AtlasError {
+    #[error("Failed to read the atlas definition: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid atlas definition: {0}")]
+    Definition(#[from] serde_yaml::Error),
+    #[error("Invalid sheet path: {0}")]
+    SheetPath(#[from] ParseAssetPathError),
+    #[error("Failed to load the sheet: {0}")]
+    Sheet(#[from] Box<LoadDirectError>),
+    #[error("Missing asset source: {0}")]
+    Source(#[from] MissingAssetSourceError),
+    #[error("Failed to read the pack folder: {0}")]
+    PackFolder(#[from] Box<AssetReaderError>),
+    #[error("Image {id} of size {size} can't be cut into tiles of size {tile}")]
+    PackedImageSize {
+        id: String,
+        size: UVec2,
+        tile: UVec2,
+    },
+    #[error("Invalid aseprite file: {0}")]
+    Aseprite(String),
+    #[error("Entry id is reserved: {0}")]
+    ReservedEntryId(String),
+    #[error("Layout of entry {id} is invalid: {reason}")]
+    InvalidLayout { id: String, reason: String },
+    #[error("Entry {id} reaches up to tile {extent}, but the sheet is only {grid} tiles large")]
+    EntryOutOfBounds {
+        id: String,
+        extent: UVec2,
+        grid: UVec2,
+    },
+    #[error("Clip {clip} of entry {id} is invalid: {reason}")]
+    InvalidClip {
+        id: String,
+        clip: String,
+        reason: String,
+    },
+    #[error("Autotiling of entry {id} is invalid: {reason}")]
+    InvalidAutotile { id: String, reason: String },
+    #[error("Entry {id} autotiles with {target}, which is not an entry of the atlas")]
+    UnknownAutotileConnection { id: String, target: String },
+    #[error("Palette {palette} of entry {id} is invalid: {reason}")]
+    InvalidPalette {
+        id: String,
+        palette: String,
+        reason: String,
+    },
+    #[error("Companion sheets can't be used with pack, their layout is generated")]
+    CompanionWithPack,
+    #[error("The {companion} is {size} pixels, but the sheet is {sheet}")]
+    CompanionSize {
+        companion: &'static str,
+        size: UVec2,
+        sheet: UVec2,
+    },
+    #[error("Baked atlases are packed already, grid can't be used with pack")]
+    BakedWithPack,
+    #[error(
+        "Baked image is {size} (width, height, layers), but the grid and tile size need {expected}"
+    )]
+    BakedSize { size: UVec3, expected: UVec3 },
+    #[error("The atlas or its stacked image is not loaded")]
+    AtlasNotLoaded,
+    #[error("Inserted tiles take {tile} bytes per layer, but the layers of the atlas take {layer}")]
+    LayerSize { tile: usize, layer: usize },
+    #[error("Sheet has to be a 2D texture, found: {0:?}")]
+    UnsupportedDimension(TextureDimension),
+    #[error("Sheet has to be a single layer, found: {0} layers")]
+    AlreadyLayered(u32),
+    #[error("Sheet size {sheet} is not a multiple of the tile size {tile}")]
+    TileSizeMismatch { sheet: UVec2, tile: UVec2 },
+    #[error("Tile size {tile} is not a multiple of the compression block size {block}")]
+    TileNotBlockAligned { tile: UVec2, block: UVec2 },
+    #[error("Texture format is not supported for stacking: {0:?}")]
+    UnsupportedFormat(TextureFormat),
+    #[error("Sheet has no pixel data on the CPU")]
+    NoCpuData,
+}
@@ -0,0 +1,435 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy::asset::AssetLoader;
+use bevy::asset::AssetPath;
+use bevy::asset::LoadContext;
+use bevy::asset::io::Reader;
+use bevy::image::ImageLoaderSettings;
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::StreamExt;
+
+use crate::*;
+
+/// Label of the stacked array texture in an atlas file, entries are labeled by their id
+pub const STACKED_IMAGE_LABEL: &str = "stacked";
+
+/// Label of the palette table in an atlas file, see [`palette_table`]
+pub const PALETTE_IMAGE_LABEL: &str = "palettes";
+
+/// Labels of the stacked companion sheets in an atlas file
+pub const NORMAL_MAP_LABEL: &str = "normal_map";
+pub const EMISSIVE_MAP_LABEL: &str = "emissive_map";
+
+/// Label of the [`HitMasks`] of the stacked texture in an atlas file
+pub const HIT_MASKS_LABEL: &str = "hit_masks";
+
+/// Sheets laid out like the color sheet, stacked into layers with the same indices
+#[derive(Default)]
+pub struct CompanionSheets {
+    pub normal_map: Option<Image>,
+    pub emissive_map: Option<Image>,
+}
+
+/// Array textures of an atlas before they are added as labeled assets, see [`stack_atlas`]
+pub struct StackedAtlas {
+    pub image: Image,
+    /// Size of the sheet in tiles
+    pub grid: UVec2,
+    pub companions: CompanionSheets,
+}
+
+/// Loads `*.meta.yml` atlas definitions.
+/// The sheet is loaded as a dependency, so editing either file reloads the atlas.
+#[derive(TypePath)]
+pub struct AtlasLoader {
+    /// Lists the folders of packed atlases
+    asset_server: AssetServer,
+}
+
+impl FromWorld for AtlasLoader {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            asset_server: world.resource::<AssetServer>().clone(),
+        }
+    }
+}
+
+impl AssetLoader for AtlasLoader {
+    type Asset = Atlas;
+    type Settings = ();
+    type Error = AtlasError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Atlas, AtlasError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let definition: AtlasDefinition = serde_yaml::from_slice(&bytes)?;
+
+        let (sheet, packed) = match &definition.pack {
+            Some(_) if definition.normal_map.is_some() || definition.emissive_map.is_some() => {
+                return Err(AtlasError::CompanionWithPack);
+            }
+            Some(_) if definition.grid.is_some() => return Err(AtlasError::BakedWithPack),
+            Some(pack) => {
+                let images = self.load_pack_folder(pack, load_context).await?;
+                pack_images(images, pack, definition.tile_size)?
+            }
+            None => {
+                let sheet_path = load_context.path().resolve_embed(
+                    &definition
+                        .image
+                        .clone()
+                        .unwrap_or_else(|| sibling_sheet(load_context.path().path())),
+                )?;
+                let sheet = load_context
+                    .loader()
+                    .immediate()
+                    .load::<Image>(sheet_path)
+                    .await
+                    .map_err(Box::new)?;
+                (sheet.take(), default())
+            }
+        };
+
+        let companions = CompanionSheets {
+            // normals are vectors, not colors
+            normal_map: load_companion(load_context, definition.normal_map.as_deref(), false)
+                .await?,
+            emissive_map: load_companion(load_context, definition.emissive_map.as_deref(), true)
+                .await?,
+        };
+
+        let stacked = match definition.grid {
+            Some(grid) => {
+                let mut baked =
+                    |image| check_baked(image, definition.tile_size, grid, &definition.stacking);
+                StackedAtlas {
+                    image: baked(sheet)?,
+                    grid,
+                    companions: CompanionSheets {
+                        normal_map: companions.normal_map.map(&mut baked).transpose()?,
+                        emissive_map: companions.emissive_map.map(&mut baked).transpose()?,
+                    },
+                }
+            }
+            None => stack_atlas(
+                &sheet,
+                definition.tile_size,
+                &definition.stacking,
+                companions,
+            )?,
+        };
+
+        build_atlas(
+            load_context,
+            stacked,
+            definition.tile_size,
+            definition.stacking.extrude,
+            merge_packed(packed, definition.entries),
+        )
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["meta.yml"]
+    }
+}
+
+impl AtlasLoader {
+    /// Loads every png in the pack folder, keyed by file stem
+    async fn load_pack_folder(
+        &self,
+        pack: &PackDefinition,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<BTreeMap<String, Image>, AtlasError> {
+        let folder = load_context.path().resolve_embed(&pack.folder)?;
+        let mut paths = self
+            .asset_server
+            .get_source(folder.source())?
+            .reader()
+            .read_directory(folder.path())
+            .await
+            .map_err(Box::new)?;
+
+        let mut images = BTreeMap::new();
+        while let Some(path) = paths.next().await {
+            if path.extension().is_none_or(|ext| ext != "png") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let id = id.to_string();
+            let image = load_context
+                .loader()
+                .immediate()
+                .load::<Image>(
+                    AssetPath::from_path(&path).with_source(folder.source().clone_owned()),
+                )
+                .await
+                .map_err(Box::new)?;
+            images.insert(id, image.take());
+        }
+        Ok(images)
+    }
+}
+
+async fn load_companion(
+    load_context: &mut LoadContext<'_>,
+    path: Option<&str>,
+    is_srgb: bool,
+) -> Result<Option<Image>, AtlasError> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let path = load_context.path().resolve_embed(path)?;
+    let image = load_context
+        .loader()
+        .with_settings(move |settings: &mut ImageLoaderSettings| settings.is_srgb = is_srgb)
+        .immediate()
+        .load::<Image>(path)
+        .await
+        .map_err(Box::new)?;
+    Ok(Some(image.take()))
+}
+
+/// Entries written in the definition on top of the ones generated by `pack`,
+/// offsets of entries named like a packed image are relative to that image
+pub fn merge_packed(
+    packed: BTreeMap<String, AtlasEntryDefinition>,
+    entries: BTreeMap<String, AtlasEntryDefinition>,
+) -> BTreeMap<String, AtlasEntryDefinition> {
+    let mut merged = packed;
+    for (id, mut entry) in entries {
+        if let Some(generated) = merged.get(&id) {
+            entry.offset += generated.offset;
+        }
+        merged.insert(id, entry);
+    }
+    merged
+}
+
+/// Stacks `sheet` and its companions into layers with the same indices
+pub fn stack_atlas(
+    sheet: &Image,
+    tile_size: UVec2,
+    options: &StackingOptions,
+    companions: CompanionSheets,
+) -> Result<StackedAtlas, AtlasError> {
+    let (image, grid) = stack_sheet(sheet, tile_size, options)?;
+    let stack_companion = |companion: &'static str, image: Option<Image>| {
+        let Some(image) = image else {
+            return Ok(None);
+        };
+        if image.size() != sheet.size() {
+            return Err(AtlasError::CompanionSize {
+                companion,
+                size: image.size(),
+                sheet: sheet.size(),
+            });
+        }
+        Ok(Some(stack_sheet(&image, tile_size, options)?.0))
+    };
+    Ok(StackedAtlas {
+        image,
+        grid,
+        companions: CompanionSheets {
+            normal_map: stack_companion(NORMAL_MAP_LABEL, companions.normal_map)?,
+            emissive_map: stack_companion(EMISSIVE_MAP_LABEL, companions.emissive_map)?,
+        },
+    })
+}
+
+/// Checks all `entries` against the stacked `grid`, including their ids
+pub fn validate_entries(
+    entries: &BTreeMap<String, AtlasEntryDefinition>,
+    grid: UVec2,
+) -> Result<(), AtlasError> {
+    let reserved = [
+        STACKED_IMAGE_LABEL,
+        PALETTE_IMAGE_LABEL,
+        NORMAL_MAP_LABEL,
+        EMISSIVE_MAP_LABEL,
+        HIT_MASKS_LABEL,
+    ];
+    for (id, entry) in entries {
+        if reserved.contains(&id.as_str()) {
+            return Err(AtlasError::ReservedEntryId(id.clone()));
+        }
+        validate_entry(id.clone(), entry, grid)?;
+        validate_connections(id, entry, |target| entries.contains_key(target))?;
+    }
+    Ok(())
+}
+
+/// Checks that the autotile connections of `entry` name entries of its atlas
+pub(crate) fn validate_connections(
+    id: &str,
+    entry: &AtlasEntryDefinition,
+    exists: impl Fn(&str) -> bool,
+) -> Result<(), AtlasError> {
+    let connects_to = entry.autotile.iter().flat_map(|a| &a.connects_to);
+    match connects_to.into_iter().find(|target| !exists(target)) {
+        Some(target) => Err(AtlasError::UnknownAutotileConnection {
+            id: id.to_string(),
+            target: target.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Adds the `stacked` textures and the validated `entries` as labeled assets
+pub(crate) fn build_atlas(
+    load_context: &mut LoadContext<'_>,
+    stacked: StackedAtlas,
+    tile_size: UVec2,
+    extrude: u32,
+    entries: BTreeMap<String, AtlasEntryDefinition>,
+) -> Result<Atlas, AtlasError> {
+    validate_entries(&entries, stacked.grid)?;
+    let grid = stacked.grid;
+    let hit_masks = load_context.add_labeled_asset(
+        HIT_MASKS_LABEL.to_string(),
+        HitMasks::new(&stacked.image, extrude),
+    );
+    let image = load_context.add_labeled_asset(STACKED_IMAGE_LABEL.to_string(), stacked.image);
+    let mut add_companion = |label: &str, companion: Option<Image>| {
+        companion.map(|companion| load_context.add_labeled_asset(label.to_string(), companion))
+    };
+    let normal_map = add_companion(NORMAL_MAP_LABEL, stacked.companions.normal_map);
+    let emissive_map = add_companion(EMISSIVE_MAP_LABEL, stacked.companions.emissive_map);
+    let (table, mut palette_rows) = palette_table(&entries);
+    let palettes = load_context.add_labeled_asset(PALETTE_IMAGE_LABEL.to_string(), table);
+
+    let entries = entries
+        .into_iter()
+        .map(|(id, entry)| {
+            let palette_rows = palette_rows.remove(&id).unwrap_or_default();
+            let id: Arc<str> = id.into();
+            let handle = load_context.add_labeled_asset(
+                id.to_string(),
+                AtlasEntry {
+                    id: id.clone(),
+                    image: image.clone(),
+                    palettes: palettes.clone(),
+                    palette_rows,
+                    normal_map: normal_map.clone(),
+                    emissive_map: emissive_map.clone(),
+                    hit_masks: hit_masks.clone(),
+                    tile_size,
+                    extrude,
+                    grid,
+                    layer_table: None,
+                    definition: entry,
+                },
+            );
+            (id, handle)
+        })
+        .collect();
+
+    Ok(Atlas {
+        tile_size,
+        extrude,
+        grid,
+        image,
+        palettes,
+        normal_map,
+        emissive_map,
+        hit_masks,
+        entries,
+        free_layers: Vec::new(),
+    })
+}
+
+/// Checks that `entry` stays within `grid` and all its clips, rules and palettes can be used
+pub(crate) fn validate_entry(
+    id: String,
+    entry: &AtlasEntryDefinition,
+    grid: UVec2,
+) -> Result<(), AtlasError> {
+    if let Err(reason) = entry.validate_layout() {
+        return Err(AtlasError::InvalidLayout { id, reason });
+    }
+    let extent = entry.extent();
+    if extent.cmpgt(grid).any() {
+        return Err(AtlasError::EntryOutOfBounds { id, extent, grid });
+    }
+    if let Some((clip, reason)) = entry
+        .clips()
+        .find_map(|(clip, animation)| Some((clip, animation.validate().err()?)))
+    {
+        return Err(AtlasError::InvalidClip {
+            clip: clip.to_string(),
+            id,
+            reason,
+        });
+    }
+    if let Some(Err(reason)) = entry
+        .autotile
+        .as_ref()
+        .map(|autotile| autotile.validate(entry.variants.as_ref()))
+    {
+        return Err(AtlasError::InvalidAutotile { id, reason });
+    }
+    if let Some(Err((palette, reason))) = entry.palettes.as_ref().map(|p| p.validate()) {
+        return Err(AtlasError::InvalidPalette {
+            id,
+            palette,
+            reason,
+        });
+    }
+    Ok(())
+}
+
+/// `belts.meta.yml` -> `belts.png`
+pub fn sibling_sheet(path: &Path) -> String {
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    let stem = file_name.strip_suffix(".meta.yml").unwrap_or(file_name);
+    format!("{stem}.png")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(connections: &[(&str, &[&str])]) -> BTreeMap<String, AtlasEntryDefinition> {
+        connections
+            .iter()
+            .map(|(id, connects_to)| {
+                let entry = AtlasEntryDefinition {
+                    variants: serde_yaml::from_str("{ stride: [1, 0], count: 16 }").unwrap(),
+                    autotile: Some(AutotileDefinition {
+                        mode: AutotileMode::Edges,
+                        connects_to: connects_to.iter().map(|id| id.to_string()).collect(),
+                        variants: default(),
+                    }),
+                    ..default()
+                };
+                (id.to_string(), entry)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn autotile_connections_name_entries_of_the_atlas() {
+        let grid = UVec2::new(16, 1);
+        let valid = entries(&[("wall", &["door"]), ("door", &["wall", "door"])]);
+        assert!(validate_entries(&valid, grid).is_ok());
+
+        let unknown = entries(&[("wall", &["door", "window"]), ("door", &[])]);
+        match validate_entries(&unknown, grid) {
+            Err(AtlasError::UnknownAutotileConnection { id, target }) => {
+                assert_eq!((id.as_str(), target.as_str()), ("wall", "window"));
+            }
+            other => panic!("expected an unknown connection, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,299 @@
+//! Materials drawing array layers selected per vertex, used by the [`AtlasSprite`](crate::AtlasSprite) batches
+
+use bevy::asset::AssetPath;
+use bevy::asset::embedded_asset;
+use bevy::asset::embedded_path;
+use bevy::mesh::MeshVertexAttribute;
+use bevy::mesh::MeshVertexBufferLayoutRef;
+use bevy::pbr::MaterialPipeline;
+use bevy::pbr::MaterialPipelineKey;
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+use bevy::render::render_resource::AsBindGroup;
+use bevy::render::render_resource::RenderPipelineDescriptor;
+use bevy::render::render_resource::ShaderType;
+use bevy::render::render_resource::SpecializedMeshPipelineError;
+use bevy::render::render_resource::VertexFormat;
+use bevy::shader::ShaderRef;
+use bevy::sprite_render::AlphaMode2d;
+use bevy::sprite_render::Material2d;
+use bevy::sprite_render::Material2dKey;
+use bevy::sprite_render::Material2dPlugin;
+
+/// Array layer to sample, the same for all vertices of a quad
+pub const ATTRIBUTE_ATLAS_LAYER: MeshVertexAttribute =
+    MeshVertexAttribute::new("AtlasLayer", 988_540_917, VertexFormat::Uint32);
+
+/// Row of the palette table to recolor with, 0 keeps the colors of the sheet
+pub const ATTRIBUTE_ATLAS_PALETTE: MeshVertexAttribute =
+    MeshVertexAttribute::new("AtlasPalette", 988_540_918, VertexFormat::Uint32);
+
+/// Bit 0 set for sprites flipped horizontally, bit 1 vertically, so normals can be mirrored with them
+pub const ATTRIBUTE_ATLAS_FLIP: MeshVertexAttribute =
+    MeshVertexAttribute::new("AtlasFlip", 988_540_919, VertexFormat::Uint32);
+
+pub(crate) struct AtlasMaterialPlugin;
+
+impl Plugin for AtlasMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        embedded_asset!(app, "atlas_sprite.wgsl");
+
+        app.add_plugins((
+            Material2dPlugin::<AtlasMaterial2d>::default(),
+            MaterialPlugin::<AtlasMaterial>::default(),
+        ))
+        .init_resource::<AtlasLight>()
+        .add_systems(PostUpdate, (update_atlas_light, refresh_atlas_materials));
+    }
+}
+
+/// Lights atlases with a normal map, in sprite space: x right, y up and z towards the viewer.
+/// The default shines straight at the sprites, so they look as drawn.
+#[derive(Resource, ShaderType, Debug, Clone)]
+pub struct AtlasLight {
+    /// Points towards the light
+    pub direction: Vec3,
+    pub color: LinearRgba,
+    /// Added to the diffuse light, so surfaces facing away don't turn black
+    pub ambient: LinearRgba,
+}
+
+impl Default for AtlasLight {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::Z,
+            color: LinearRgba::WHITE,
+            ambient: LinearRgba::BLACK,
+        }
+    }
+}
+
+fn update_atlas_light(
+    light: Res<AtlasLight>,
+    mut materials_2d: ResMut<Assets<AtlasMaterial2d>>,
+    mut materials_3d: ResMut<Assets<AtlasMaterial>>,
+) {
+    if !light.is_changed() {
+        return;
+    }
+    for (_, material) in materials_2d.iter_mut() {
+        material.light = light.clone();
+    }
+    for (_, material) in materials_3d.iter_mut() {
+        material.light = light.clone();
+    }
+}
+
+/// Materials keep the textures they were prepared with, so the ones using a texture that changed in place,
+/// like an atlas growing in [`AtlasComposer::insert`](crate::AtlasComposer::insert), are prepared again
+fn refresh_atlas_materials(
+    mut image_events: MessageReader<AssetEvent<Image>>,
+    mut materials_2d: ResMut<Assets<AtlasMaterial2d>>,
+    mut materials_3d: ResMut<Assets<AtlasMaterial>>,
+) {
+    let modified = image_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+    if modified.is_empty() {
+        return;
+    }
+    let uses_modified = |textures: [Option<&Handle<Image>>; 3]| {
+        textures
+            .into_iter()
+            .flatten()
+            .any(|texture| modified.contains(&texture.id()))
+    };
+
+    let stale_2d = materials_2d
+        .iter()
+        .filter(|(_, m)| {
+            uses_modified([
+                Some(&m.image),
+                m.normal_map.as_ref(),
+                m.emissive_map.as_ref(),
+            ])
+        })
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    for id in stale_2d {
+        materials_2d.get_mut(id);
+    }
+    let stale_3d = materials_3d
+        .iter()
+        .filter(|(_, m)| {
+            uses_modified([
+                Some(&m.image),
+                m.normal_map.as_ref(),
+                m.emissive_map.as_ref(),
+            ])
+        })
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    for id in stale_3d {
+        materials_3d.get_mut(id);
+    }
+}
+
+/// Companion maps the shader is specialized for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasMaterialKey {
+    normal_map: bool,
+    emissive_map: bool,
+}
+
+impl AtlasMaterialKey {
+    fn shader_defs(self, descriptor: &mut RenderPipelineDescriptor) {
+        let Some(fragment) = descriptor.fragment.as_mut() else {
+            return;
+        };
+        if self.normal_map {
+            fragment.shader_defs.push("ATLAS_NORMAL_MAP".into());
+        }
+        if self.emissive_map {
+            fragment.shader_defs.push("ATLAS_EMISSIVE_MAP".into());
+        }
+    }
+}
+
+impl From<&AtlasMaterial2d> for AtlasMaterialKey {
+    fn from(material: &AtlasMaterial2d) -> Self {
+        Self {
+            normal_map: material.normal_map.is_some(),
+            emissive_map: material.emissive_map.is_some(),
+        }
+    }
+}
+
+impl From<&AtlasMaterial> for AtlasMaterialKey {
+    fn from(material: &AtlasMaterial) -> Self {
+        Self {
+            normal_map: material.normal_map.is_some(),
+            emissive_map: material.emissive_map.is_some(),
+        }
+    }
+}
+
+fn shader() -> ShaderRef {
+    ShaderRef::Path(
+        AssetPath::from_path_buf(embedded_path!("atlas_sprite.wgsl")).with_source("embedded"),
+    )
+}
+
+/// Vertex layout shared by both materials: position, uv, tint, layer, palette and flip
+fn vertex_buffer(
+    descriptor: &mut RenderPipelineDescriptor,
+    layout: &MeshVertexBufferLayoutRef,
+) -> Result<(), SpecializedMeshPipelineError> {
+    descriptor.vertex.buffers = vec![layout.0.get_layout(&[
+        Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+        Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+        Mesh::ATTRIBUTE_COLOR.at_shader_location(2),
+        ATTRIBUTE_ATLAS_LAYER.at_shader_location(3),
+        ATTRIBUTE_ATLAS_PALETTE.at_shader_location(4),
+        ATTRIBUTE_ATLAS_FLIP.at_shader_location(5),
+    ])?];
+    Ok(())
+}
+
+/// Draws alpha blended 2D sprites, see [`Mesh2d`]
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[bind_group_data(AtlasMaterialKey)]
+pub struct AtlasMaterial2d {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub image: Handle<Image>,
+    /// See [`palette_table`](crate::palette_table)
+    #[texture(2)]
+    pub palettes: Handle<Image>,
+    /// Companion sheets, sampled with the layer and sampler of `image`
+    #[texture(3, dimension = "2d_array")]
+    pub normal_map: Option<Handle<Image>>,
+    #[texture(4, dimension = "2d_array")]
+    pub emissive_map: Option<Handle<Image>>,
+    #[uniform(5)]
+    pub light: AtlasLight,
+}
+
+impl Material2d for AtlasMaterial2d {
+    fn vertex_shader() -> ShaderRef {
+        shader()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        shader()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode2d {
+        AlphaMode2d::Blend
+    }
+
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        key: Material2dKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        key.bind_group_data.shader_defs(descriptor);
+        vertex_buffer(descriptor, layout)
+    }
+}
+
+/// Draws unlit 3D sprites, see [`Mesh3d`].
+/// Pixels below half opacity are discarded, so sprites need no sorting against each other.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+#[bind_group_data(AtlasMaterialKey)]
+pub struct AtlasMaterial {
+    #[texture(0, dimension = "2d_array")]
+    #[sampler(1)]
+    pub image: Handle<Image>,
+    /// See [`palette_table`](crate::palette_table)
+    #[texture(2)]
+    pub palettes: Handle<Image>,
+    /// Companion sheets, sampled with the layer and sampler of `image`
+    #[texture(3, dimension = "2d_array")]
+    pub normal_map: Option<Handle<Image>>,
+    #[texture(4, dimension = "2d_array")]
+    pub emissive_map: Option<Handle<Image>>,
+    #[uniform(5)]
+    pub light: AtlasLight,
+}
+
+impl Material for AtlasMaterial {
+    fn vertex_shader() -> ShaderRef {
+        shader()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        shader()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Mask(0.5)
+    }
+
+    /// the prepass and shadow shaders don't know the atlas vertex layout
+    fn enable_prepass() -> bool {
+        false
+    }
+
+    fn enable_shadows() -> bool {
+        false
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        descriptor.vertex.shader_defs.push("ATLAS_3D".into());
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader_defs.push("ATLAS_3D".into());
+        }
+        key.bind_group_data.shader_defs(descriptor);
+        vertex_buffer(descriptor, layout)
+    }
+}
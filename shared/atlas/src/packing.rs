@@ -0,0 +1,308 @@
+//! Arranging loose images into one sheet, see [`PackDefinition`]
+
+use std::collections::BTreeMap;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+
+use crate::*;
+
+/// Places `images` on a sheet made of whole tiles and generates an entry for each of them.
+/// Entries are named after the id they were passed with, so they stay the same no matter where they end up.
+pub fn pack_images(
+    images: BTreeMap<String, Image>,
+    pack: &PackDefinition,
+    tile_size: UVec2,
+) -> Result<(Image, BTreeMap<String, AtlasEntryDefinition>), AtlasError> {
+    let images = images
+        .into_iter()
+        .map(|(id, image)| {
+            let image = image
+                .convert(TextureFormat::Rgba8UnormSrgb)
+                .ok_or_else(|| AtlasError::UnsupportedFormat(image.texture_descriptor.format))?;
+            let size = image.size();
+            if tile_size.min_element() == 0 || (size % tile_size).max_element() != 0 {
+                return Err(AtlasError::PackedImageSize {
+                    id,
+                    size,
+                    tile: tile_size,
+                });
+            }
+            Ok((id, image))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let placements = shelf_pack(
+        images
+            .iter()
+            .map(|(_, image)| image.size() / tile_size)
+            .collect(),
+    );
+    let grid = placements
+        .iter()
+        .zip(&images)
+        .map(|(offset, (_, image))| offset + image.size() / tile_size)
+        .fold(UVec2::ONE, UVec2::max);
+
+    let sheet_size = grid * tile_size;
+    let mut sheet = Image::new_fill(
+        Extent3d {
+            width: sheet_size.x,
+            height: sheet_size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    let sheet_data = sheet.data.as_mut().ok_or(AtlasError::NoCpuData)?;
+
+    let mut entries = BTreeMap::new();
+    for ((id, image), offset) in images.into_iter().zip(placements) {
+        let data = image.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+        let row_bytes = image.width() as usize * 4;
+        let origin = offset * tile_size;
+        for y in 0..image.height() as usize {
+            let dst = ((origin.y as usize + y) * sheet_size.x as usize + origin.x as usize) * 4;
+            sheet_data[dst..dst + row_bytes]
+                .copy_from_slice(&data[y * row_bytes..(y + 1) * row_bytes]);
+        }
+
+        let tiles = image.size() / tile_size;
+        let (size, animation) = match pack.strips.get(&id) {
+            Some(strip) if strip.count == 0 || !tiles.x.is_multiple_of(strip.count) => {
+                return Err(AtlasError::PackedImageSize {
+                    id,
+                    size: image.size(),
+                    tile: tile_size * UVec2::new(strip.count, 1),
+                });
+            }
+            Some(strip) => {
+                let frame = UVec2::new(tiles.x / strip.count, tiles.y);
+                let animation = AnimationDefinition::uniform(
+                    strip.frame_duration,
+                    UVec2::new(frame.x, 0),
+                    strip.count,
+                );
+                (frame, Some(animation))
+            }
+            None => (tiles, None),
+        };
+        entries.insert(
+            id,
+            AtlasEntryDefinition {
+                size,
+                offset,
+                animation,
+                animations: default(),
+                variants: None,
+                rotation: None,
+                autotile: None,
+                palettes: None,
+            },
+        );
+    }
+
+    Ok((sheet, entries))
+}
+
+/// Fills rows left to right, tallest items first, so little space is left between them.
+/// The sheet is roughly square but at least as wide as the widest item.
+fn shelf_pack(sizes: Vec<UVec2>) -> Vec<UVec2> {
+    let area: u32 = sizes.iter().map(|size| size.element_product()).sum();
+    let width = sizes
+        .iter()
+        .map(|size| size.x)
+        .max()
+        .unwrap_or(1)
+        .max((area as f32).sqrt().ceil() as u32);
+
+    let mut order = (0..sizes.len()).collect::<Vec<_>>();
+    // stable, so equally tall items keep their order by id
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].y));
+
+    let mut placements = vec![UVec2::ZERO; sizes.len()];
+    let mut cursor = UVec2::ZERO;
+    let mut row_height = 0;
+    for i in order {
+        let size = sizes[i];
+        if cursor.x + size.x > width {
+            cursor = UVec2::new(0, cursor.y + row_height);
+            row_height = 0;
+        }
+        placements[i] = cursor;
+        cursor.x += size.x;
+        row_height = row_height.max(size.y);
+    }
+    placements
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const TILE: UVec2 = UVec2::splat(2);
+
+    fn pack(strips: &[(&str, u32)]) -> PackDefinition {
+        PackDefinition {
+            folder: "sprites".to_string(),
+            strips: strips
+                .iter()
+                .map(|(id, count)| {
+                    let strip = StripDefinition {
+                        count: *count,
+                        frame_duration: Duration::from_millis(100),
+                    };
+                    (id.to_string(), strip)
+                })
+                .collect(),
+        }
+    }
+
+    /// Image of `tiles` whose pixels all have the color `value`
+    fn image(tiles: UVec2, value: u8) -> Image {
+        let size = tiles * TILE;
+        Image::new_fill(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            &[value; 4],
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        )
+    }
+
+    fn images(images: &[(&str, UVec2, u8)]) -> BTreeMap<String, Image> {
+        images
+            .iter()
+            .map(|(id, tiles, value)| (id.to_string(), image(*tiles, *value)))
+            .collect()
+    }
+
+    /// Whether every tile of `entry` in `sheet` has the color `value`
+    fn shows(sheet: &Image, entry: &AtlasEntryDefinition, value: u8) -> bool {
+        let tiles = entry.extent() - entry.offset;
+        (0..tiles.element_product() * TILE.element_product()).all(|pixel| {
+            let pixel = UVec2::new(pixel % (tiles.x * TILE.x), pixel / (tiles.x * TILE.x));
+            let pixel = entry.offset * TILE + pixel;
+            sheet.pixel_bytes(pixel.extend(0)) == Some(&[value; 4][..])
+        })
+    }
+
+    /// Asserts that no two placed rectangles overlap and all of them stay within `width`
+    fn assert_disjoint(sizes: &[UVec2], placements: &[UVec2], width: u32) {
+        let rects = sizes
+            .iter()
+            .zip(placements)
+            .map(|(size, offset)| URect::from_corners(*offset, offset + size))
+            .collect::<Vec<_>>();
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.max.x <= width, "{a:?} is wider than {width}");
+            for b in &rects[i + 1..] {
+                assert!(a.intersect(*b).is_empty(), "{a:?} overlaps {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn shelves_start_with_the_tallest_items() {
+        let sizes = vec![
+            UVec2::new(1, 1),
+            UVec2::new(2, 3),
+            UVec2::new(1, 2),
+            UVec2::new(1, 1),
+        ];
+        let placements = shelf_pack(sizes.clone());
+        assert_eq!(
+            placements,
+            [
+                UVec2::new(3, 0),
+                UVec2::new(0, 0),
+                UVec2::new(2, 0),
+                UVec2::new(0, 3),
+            ]
+        );
+        assert_disjoint(&sizes, &placements, 4);
+    }
+
+    #[test]
+    fn items_that_overflow_a_shelf_start_the_next_one() {
+        // 4 tiles of area make the sheet 2 wide, the third item doesn't fit next to the others
+        let sizes = vec![UVec2::ONE, UVec2::ONE, UVec2::ONE, UVec2::ONE];
+        let placements = shelf_pack(sizes.clone());
+        assert_eq!(placements, [UVec2::ZERO, UVec2::X, UVec2::Y, UVec2::ONE]);
+
+        // a single wide item sets the width for all shelves
+        let sizes = vec![UVec2::new(5, 1), UVec2::new(3, 1), UVec2::new(3, 1)];
+        let placements = shelf_pack(sizes.clone());
+        assert_eq!(placements, [UVec2::ZERO, UVec2::Y, UVec2::new(0, 2)]);
+        assert_disjoint(&sizes, &placements, 5);
+    }
+
+    #[test]
+    fn entries_keep_their_ids_across_repacks() {
+        let before = images(&[("crate", UVec2::ONE, 10), ("tree", UVec2::new(1, 2), 20)]);
+        let (sheet, entries) = pack_images(before, &pack(&[]), TILE).unwrap();
+        assert!(shows(&sheet, &entries["crate"], 10));
+        assert!(shows(&sheet, &entries["tree"], 20));
+
+        // a new, taller image moves the others, but their ids still point at their pixels
+        let after = images(&[
+            ("crate", UVec2::ONE, 10),
+            ("tree", UVec2::new(1, 2), 20),
+            ("tower", UVec2::new(2, 3), 30),
+        ]);
+        let (sheet, repacked) = pack_images(after, &pack(&[]), TILE).unwrap();
+        assert_eq!(
+            repacked.keys().collect::<Vec<_>>(),
+            ["crate", "tower", "tree"]
+        );
+        assert_ne!(repacked["tree"].offset, entries["tree"].offset);
+        assert!(shows(&sheet, &repacked["crate"], 10));
+        assert!(shows(&sheet, &repacked["tree"], 20));
+        assert!(shows(&sheet, &repacked["tower"], 30));
+    }
+
+    #[test]
+    fn strips_become_animated_entries() {
+        let strips = images(&[("belt", UVec2::new(4, 1), 40)]);
+        let (_, entries) = pack_images(strips, &pack(&[("belt", 4)]), TILE).unwrap();
+        let belt = &entries["belt"];
+        assert_eq!(belt.size, UVec2::ONE);
+        let animation = belt.animation.as_ref().unwrap();
+        assert_eq!(animation.frame_count(), 4);
+        assert_eq!(animation.frame_offset(3), UVec2::new(3, 0));
+
+        let strips = images(&[("belt", UVec2::new(4, 1), 40)]);
+        assert!(matches!(
+            pack_images(strips, &pack(&[("belt", 3)]), TILE),
+            Err(AtlasError::PackedImageSize { .. })
+        ));
+    }
+
+    #[test]
+    fn images_must_be_whole_tiles() {
+        let mut pebble = image(UVec2::ONE, 0);
+        pebble.resize(Extent3d {
+            width: 3,
+            height: 2,
+            depth_or_array_layers: 1,
+        });
+        let odd = BTreeMap::from([("pebble".to_string(), pebble)]);
+        match pack_images(odd, &pack(&[]), TILE) {
+            Err(AtlasError::PackedImageSize { id, size, .. }) => {
+                assert_eq!((id.as_str(), size), ("pebble", UVec2::new(3, 2)));
+            }
+            other => panic!("expected a size error, got {other:?}"),
+        }
+    }
+}
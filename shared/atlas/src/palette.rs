@@ -0,0 +1,71 @@
+//! Lookup texture for the color swaps of [`PalettesDefinition`]s
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use bevy::asset::RenderAssetUsages;
+use bevy::platform::collections::HashMap;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+
+use crate::*;
+
+/// Lays out the swaps of all `entries` one per row, as alternating source and replacement texels.
+/// Row 0 stays empty, it is used by sprites without a palette.
+/// Returns the texture and the row of each swap by entry id.
+pub fn palette_table(
+    entries: &BTreeMap<String, AtlasEntryDefinition>,
+) -> (Image, BTreeMap<String, HashMap<Arc<str>, u32>>) {
+    let palettes = entries
+        .iter()
+        .filter_map(|(id, entry)| Some((id, entry.palettes.as_ref()?)))
+        .collect::<Vec<_>>();
+
+    let width = palettes
+        .iter()
+        .map(|(_, palettes)| palettes.source.len() as u32 * 2)
+        .fold(2, u32::max);
+    let rows = 1 + palettes
+        .iter()
+        .map(|(_, palettes)| palettes.swaps.len() as u32)
+        .sum::<u32>();
+
+    // transparent texels end the list of sources
+    let mut data = vec![0; (width * rows * 4) as usize];
+    let mut table = BTreeMap::new();
+    let mut row = 1;
+    for (id, palettes) in palettes {
+        let mut swaps = HashMap::new();
+        for (name, colors) in &palettes.swaps {
+            let texels = palettes
+                .source
+                .iter()
+                .zip(colors)
+                .flat_map(|(source, replacement)| {
+                    [source.to_u8_array(), replacement.to_u8_array()]
+                });
+            let start = (row * width * 4) as usize;
+            for (i, texel) in texels.enumerate() {
+                data[start + i * 4..][..4].copy_from_slice(&texel);
+            }
+            swaps.insert(Arc::from(name.as_str()), row);
+            row += 1;
+        }
+        table.insert(id.clone(), swaps);
+    }
+
+    let image = Image::new(
+        Extent3d {
+            width,
+            height: rows,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    (image, table)
+}
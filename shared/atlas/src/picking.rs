@@ -0,0 +1,190 @@
+//! Per pixel picking of [`AtlasSprite`]s through their atlas' [`HitMasks`]
+
+use std::cmp::Ordering;
+
+use bevy::picking::backend::prelude::*;
+use bevy::prelude::*;
+
+use crate::*;
+
+/// Pixels with a higher alpha count as part of the sprite, like the default of bevy's sprite picking
+pub const HIT_MASK_ALPHA: f32 = 0.1;
+
+/// Opaque pixels of every layer of a stacked texture, one bit per pixel of the tile without its extrusion.
+/// Created with the atlas, layers of unreadable formats (block compressed) are fully opaque.
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct HitMasks {
+    pub tile_size: UVec2,
+    extrude: u32,
+    /// Row by row, each layer starts at a new word
+    bits: Vec<u64>,
+}
+
+impl HitMasks {
+    pub fn new(stacked: &Image, extrude: u32) -> Self {
+        let mut masks = Self {
+            tile_size: stacked.size() - 2 * extrude,
+            extrude,
+            bits: Vec::new(),
+        };
+        for layer in 0..stacked.texture_descriptor.size.depth_or_array_layers {
+            masks.update_layer(stacked, layer);
+        }
+        masks
+    }
+
+    /// Reads `layer` of `stacked` again after it was written, growing the masks for new layers
+    pub fn update_layer(&mut self, stacked: &Image, layer: u32) {
+        let words = self.layer_words();
+        let start = layer as usize * words;
+        if self.bits.len() < start + words {
+            self.bits.resize(start + words, 0);
+        }
+        let tile = stacked_layer(stacked, layer);
+        let bits = &mut self.bits[start..][..words];
+        for y in 0..self.tile_size.y {
+            for x in 0..self.tile_size.x {
+                let opaque = tile.as_ref().is_none_or(|tile| {
+                    tile.get_color_at(x + self.extrude, y + self.extrude)
+                        .is_ok_and(|color| color.alpha() > HIT_MASK_ALPHA)
+                });
+                let i = (y * self.tile_size.x + x) as usize;
+                if opaque {
+                    bits[i / 64] |= 1 << (i % 64);
+                } else {
+                    bits[i / 64] &= !(1 << (i % 64));
+                }
+            }
+        }
+    }
+
+    /// Whether `pixel` of `layer` is opaque, counted from the top left of the tile.
+    /// Layers the masks don't know yet count as opaque.
+    pub fn contains(&self, layer: u32, pixel: UVec2) -> bool {
+        if pixel.cmpge(self.tile_size).any() {
+            return false;
+        }
+        let i = (pixel.y * self.tile_size.x + pixel.x) as usize;
+        self.bits
+            .get(layer as usize * self.layer_words() + i / 64)
+            .is_none_or(|word| word & (1 << (i % 64)) != 0)
+    }
+
+    fn layer_words(&self) -> usize {
+        (self.tile_size.element_product() as usize).div_ceil(64)
+    }
+}
+
+/// Picking backend for [`AtlasSprite`]s in 2D and 3D, only sprites with a [`Pickable`] are considered.
+/// Hits are limited to the opaque pixels of the tiles shown, see [`AtlasPickingSettings`].
+pub struct AtlasPickingPlugin;
+
+impl Plugin for AtlasPickingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AtlasPickingSettings>()
+            .add_systems(PreUpdate, atlas_picking.in_set(PickingSystems::Backend));
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct AtlasPickingSettings {
+    /// Only picks through cameras with an [`AtlasPickingCamera`]
+    pub require_markers: bool,
+    /// Checks the [`HitMasks`], otherwise the whole quad of every tile is hit
+    pub per_pixel: bool,
+}
+
+impl Default for AtlasPickingSettings {
+    fn default() -> Self {
+        Self {
+            require_markers: false,
+            per_pixel: true,
+        }
+    }
+}
+
+/// Marks the cameras used by the [`AtlasPickingPlugin`] when [`AtlasPickingSettings::require_markers`] is set
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct AtlasPickingCamera;
+
+fn atlas_picking(
+    ray_map: Res<RayMap>,
+    cameras: Query<(&Camera, Has<AtlasPickingCamera>)>,
+    sprites: Query<(
+        Entity,
+        &AtlasSprite,
+        &GlobalTransform,
+        &Pickable,
+        &InheritedVisibility,
+    )>,
+    entries: Res<Assets<AtlasEntry>>,
+    hit_masks: Res<Assets<HitMasks>>,
+    settings: Res<AtlasPickingSettings>,
+    mut hits_writer: MessageWriter<PointerHits>,
+) {
+    for (ray_id, ray) in ray_map.iter() {
+        let Ok((camera, marked)) = cameras.get(ray_id.camera) else {
+            continue;
+        };
+        if !camera.is_active || (settings.require_markers && !marked) {
+            continue;
+        }
+
+        let mut hits = sprites
+            .iter()
+            .filter(|(_, _, _, _, visibility)| visibility.get())
+            .filter_map(|(entity, sprite, transform, pickable, _)| {
+                let entry = entries.get(&sprite.entry)?;
+                let distance = ray.intersect_plane(
+                    transform.translation(),
+                    InfinitePlane3d::new(transform.back()),
+                )?;
+                let position = ray.get_point(distance);
+                let local = transform.affine().inverse().transform_point3(position);
+                let (layer, pixel) = sprite_pixel(sprite, entry, local.xy())?;
+                let opaque = !settings.per_pixel
+                    || hit_masks
+                        .get(&entry.hit_masks)
+                        .is_none_or(|masks| masks.contains(layer, pixel));
+                opaque.then_some((entity, pickable, distance, position, transform.back()))
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|(_, _, a, ..), (_, _, b, ..)| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+        // everything behind the first blocking sprite is hidden
+        let blocking = hits
+            .iter()
+            .position(|(_, pickable, ..)| pickable.should_block_lower)
+            .map_or(hits.len(), |i| i + 1);
+        let picks = hits
+            .into_iter()
+            .take(blocking)
+            .map(|(entity, _, distance, position, normal)| {
+                let hit = HitData::new(ray_id.camera, distance, Some(position), Some(*normal));
+                (entity, hit)
+            })
+            .collect();
+        hits_writer.write(PointerHits::new(ray_id.pointer, picks, camera.order as f32));
+    }
+}
+
+/// Layer and tile pixel under `local`, a point in the sprite's plane, the inverse of the quads drawn for it
+fn sprite_pixel(sprite: &AtlasSprite, entry: &AtlasEntry, local: Vec2) -> Option<(u32, UVec2)> {
+    let tiles = entry.definition.drawn_size(&sprite.state).as_vec2();
+    let size = sprite
+        .custom_size
+        .unwrap_or(entry.tile_size.as_vec2() * tiles);
+    let mirror = Vec2::new(
+        if sprite.flip_x { -1.0 } else { 1.0 },
+        if sprite.flip_y { -1.0 } else { 1.0 },
+    );
+    // sheet rows grow downwards, world y upwards
+    let sheet = (local * mirror * Vec2::new(1.0, -1.0) / size + 0.5) * tiles;
+    if sheet.cmplt(Vec2::ZERO).any() || sheet.cmpge(tiles).any() {
+        return None;
+    }
+    let cell = sheet.floor();
+    let pixel = ((sheet - cell) * entry.tile_size.as_vec2()).as_uvec2();
+    let layer = entry.cell_layer(entry.definition.tile(&sprite.state) + cell.as_uvec2());
+    Some((layer, pixel.min(entry.tile_size - 1)))
+}
@@ -0,0 +1,532 @@
+//! Batched drawing of atlas tiles, see [`AtlasSprite`]
+
+use std::sync::Arc;
+
+use bevy::asset::AssetLoadError;
+use bevy::asset::AssetLoadFailedEvent;
+use bevy::asset::AssetPath;
+use bevy::asset::LoadState;
+use bevy::asset::RenderAssetUsages;
+use bevy::camera::visibility::NoFrustumCulling;
+use bevy::camera::visibility::VisibilitySystems;
+use bevy::math::FloatOrd;
+use bevy::mesh::Indices;
+use bevy::mesh::PrimitiveTopology;
+use bevy::platform::collections::HashMap;
+use bevy::platform::collections::HashSet;
+use bevy::prelude::*;
+use bevy::transform::TransformSystems;
+
+use crate::*;
+
+pub(crate) struct AtlasSpritePlugin;
+
+impl Plugin for AtlasSpritePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(AtlasMaterialPlugin)
+            .init_resource::<AtlasBatches>()
+            .init_resource::<FailedAtlases>()
+            .add_systems(
+                PostUpdate,
+                (
+                    track_failed_atlases,
+                    mark_failed_sprites,
+                    update_atlas_batches,
+                )
+                    .chain()
+                    .after(TransformSystems::Propagate)
+                    .after(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
+
+/// Draws an [`AtlasEntry`] in the local XY plane, centered on the entity, one quad per tile.
+/// All sprites of an atlas are merged into one mesh and drawn in a single call.
+/// 2D sprites are merged per `Transform` z as well, so they sort against other 2D content by z,
+/// but every distinct z of an atlas costs a draw call of its own.
+#[derive(Component, Debug, Clone)]
+#[require(Transform, Visibility)]
+pub struct AtlasSprite {
+    pub entry: Handle<AtlasEntry>,
+    /// Selects the tile of the entry to draw
+    pub state: AtlasEntryState,
+    pub color: Color,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Size of the whole entry in world units, defaults to the size of its tiles in pixels
+    pub custom_size: Option<Vec2>,
+    /// Name of one of the entry's `palettes` swaps to recolor it with
+    pub palette: Option<Arc<str>>,
+}
+
+impl AtlasSprite {
+    pub fn new(entry: Handle<AtlasEntry>) -> Self {
+        Self {
+            entry,
+            state: default(),
+            color: Color::WHITE,
+            flip_x: false,
+            flip_y: false,
+            custom_size: None,
+            palette: None,
+        }
+    }
+}
+
+/// Added to [`AtlasSprite`]s whose atlas failed to load, they show the [`AtlasFallback`] until it loads
+#[derive(Component, Debug, Clone)]
+pub struct AtlasLoadFailed {
+    pub error: Arc<AssetLoadError>,
+}
+
+/// Draws the [`AtlasSprite`] on this entity with the 3D batch of its atlas instead of the 2D one
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct AtlasSprite3d;
+
+/// Entity holding the merged mesh of all sprites sharing an atlas texture, and for 2D sprites their z
+#[derive(Component, Debug)]
+pub struct AtlasBatch {
+    pub image: AssetId<Image>,
+    pub in_3d: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct BatchKey {
+    image: AssetId<Image>,
+    in_3d: bool,
+    /// 0 in 3D, where sprites are depth tested instead of sorted
+    z: FloatOrd,
+}
+
+struct Batch {
+    entity: Entity,
+    mesh: Handle<Mesh>,
+    sprites: HashSet<Entity>,
+}
+
+#[derive(Resource, Default)]
+pub(crate) struct AtlasBatches {
+    batches: HashMap<BatchKey, Batch>,
+    /// Batch each drawn sprite is part of
+    sprite_keys: HashMap<Entity, BatchKey>,
+}
+
+impl AtlasBatches {
+    /// Removes the sprite from its batch, returns the key of the batch
+    fn leave(&mut self, sprite: Entity) -> Option<BatchKey> {
+        let key = self.sprite_keys.remove(&sprite)?;
+        if let Some(batch) = self.batches.get_mut(&key) {
+            batch.sprites.remove(&sprite);
+        }
+        Some(key)
+    }
+}
+
+/// Atlas files whose last load failed, filled from [`AssetLoadFailedEvent`]s until the file loads again.
+/// Entries are labeled assets of them and don't fail on their own.
+#[derive(Resource, Default)]
+pub struct FailedAtlases(HashMap<AssetPath<'static>, Arc<AssetLoadError>>);
+
+impl FailedAtlases {
+    pub fn get(&self, path: &AssetPath) -> Option<&Arc<AssetLoadError>> {
+        self.0.get(&path.without_label().into_owned())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&AssetPath<'static>, &Arc<AssetLoadError>)> {
+        self.0.iter()
+    }
+}
+
+/// Textures a batch is drawn with
+struct BatchTextures {
+    image: Handle<Image>,
+    palettes: Handle<Image>,
+    normal_map: Option<Handle<Image>>,
+    emissive_map: Option<Handle<Image>>,
+}
+
+impl From<&AtlasEntry> for BatchTextures {
+    fn from(entry: &AtlasEntry) -> Self {
+        Self {
+            image: entry.image.clone(),
+            palettes: entry.palettes.clone(),
+            normal_map: entry.normal_map.clone(),
+            emissive_map: entry.emissive_map.clone(),
+        }
+    }
+}
+
+impl From<&AtlasFallback> for BatchTextures {
+    fn from(fallback: &AtlasFallback) -> Self {
+        Self {
+            image: fallback.handle.clone(),
+            palettes: fallback.palettes.clone(),
+            normal_map: None,
+            emissive_map: None,
+        }
+    }
+}
+
+struct Quad {
+    corners: [Vec3; 4],
+    uvs: [[f32; 2]; 4],
+    color: [f32; 4],
+    layer: u32,
+    palette: u32,
+    flip: u32,
+}
+
+fn track_failed_atlases(
+    mut failed_events: MessageReader<AssetLoadFailedEvent<Atlas>>,
+    mut atlas_events: MessageReader<AssetEvent<Atlas>>,
+    asset_server: Res<AssetServer>,
+    mut failed: ResMut<FailedAtlases>,
+) {
+    for event in failed_events.read() {
+        failed.0.insert(
+            event.path.without_label().into_owned(),
+            Arc::new(event.error.clone()),
+        );
+    }
+    for event in atlas_events.read() {
+        if let AssetEvent::LoadedWithDependencies { id } = event
+            && let Some(path) = asset_server.get_path(*id)
+        {
+            failed.0.remove(&path.without_label().into_owned());
+        }
+    }
+}
+
+fn mark_failed_sprites(
+    mut commands: Commands,
+    sprites: Query<(Entity, &AtlasSprite, Has<AtlasLoadFailed>)>,
+    entries: Res<Assets<AtlasEntry>>,
+    failed: Res<FailedAtlases>,
+    asset_server: Res<AssetServer>,
+) {
+    for (entity, sprite, marked) in &sprites {
+        let error = match entries.contains(&sprite.entry) {
+            true => None,
+            false => match asset_server.load_state(&sprite.entry) {
+                LoadState::Failed(error) => Some(error),
+                _ => asset_server
+                    .get_path(&sprite.entry)
+                    .and_then(|path| failed.get(&path).cloned()),
+            },
+        };
+        match error {
+            Some(error) if !marked => {
+                commands.entity(entity).insert(AtlasLoadFailed { error });
+            }
+            None if marked => {
+                commands.entity(entity).remove::<AtlasLoadFailed>();
+            }
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn update_atlas_batches(
+    mut commands: Commands,
+    sprites: Query<(
+        Entity,
+        &AtlasSprite,
+        &GlobalTransform,
+        &InheritedVisibility,
+        Has<AtlasSprite3d>,
+        Has<AtlasLoadFailed>,
+    )>,
+    changed: Query<
+        Entity,
+        (
+            With<AtlasSprite>,
+            Or<(
+                Changed<AtlasSprite>,
+                Changed<GlobalTransform>,
+                Changed<InheritedVisibility>,
+                Added<AtlasSprite3d>,
+                Added<AtlasLoadFailed>,
+            )>,
+        ),
+    >,
+    mut removed: RemovedComponents<AtlasSprite>,
+    mut removed_3d: RemovedComponents<AtlasSprite3d>,
+    mut removed_failed: RemovedComponents<AtlasLoadFailed>,
+    mut entry_events: MessageReader<AssetEvent<AtlasEntry>>,
+    entries: Res<Assets<AtlasEntry>>,
+    fallback: Res<AtlasFallback>,
+    mut batches: ResMut<AtlasBatches>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials_2d: ResMut<Assets<AtlasMaterial2d>>,
+    mut materials_3d: ResMut<Assets<AtlasMaterial>>,
+    light: Res<AtlasLight>,
+) {
+    let batches = &mut *batches;
+
+    let mut dirty_sprites = changed.iter().collect::<HashSet<_>>();
+    dirty_sprites.extend(removed_3d.read());
+    dirty_sprites.extend(removed_failed.read());
+    let changed_entries = entry_events
+        .read()
+        .map(|event| match *event {
+            AssetEvent::Added { id }
+            | AssetEvent::Modified { id }
+            | AssetEvent::Removed { id }
+            | AssetEvent::Unused { id }
+            | AssetEvent::LoadedWithDependencies { id } => id,
+        })
+        .collect::<HashSet<_>>();
+    if !changed_entries.is_empty() {
+        dirty_sprites.extend(
+            sprites
+                .iter()
+                .filter(|(_, sprite, ..)| changed_entries.contains(&sprite.entry.id()))
+                .map(|(entity, ..)| entity),
+        );
+    }
+
+    // sprites moving between batches leave both of them dirty
+    let mut dirty_keys = HashSet::new();
+    for entity in removed.read() {
+        dirty_keys.extend(batches.leave(entity));
+    }
+    for entity in dirty_sprites {
+        dirty_keys.extend(batches.leave(entity));
+        let Some(key) = sprites.get(entity).ok().and_then(
+            |(_, sprite, transform, visibility, in_3d, failed)| {
+                batch_key(
+                    sprite, transform, visibility, in_3d, failed, &entries, &fallback,
+                )
+            },
+        ) else {
+            continue;
+        };
+        batches.sprite_keys.insert(entity, key);
+        batches
+            .batches
+            .entry(key)
+            .or_insert_with(|| Batch {
+                entity: Entity::PLACEHOLDER,
+                mesh: default(),
+                sprites: default(),
+            })
+            .sprites
+            .insert(entity);
+        dirty_keys.insert(key);
+    }
+
+    for key in dirty_keys {
+        let Some(batch) = batches.batches.get_mut(&key) else {
+            continue;
+        };
+        if batch.sprites.is_empty() {
+            if batch.entity != Entity::PLACEHOLDER {
+                commands.entity(batch.entity).despawn();
+            }
+            batches.batches.remove(&key);
+            continue;
+        }
+
+        let mut textures = None;
+        let mut quads = Vec::new();
+        for (_, sprite, transform, _, _, failed) in sprites.iter_many(&batch.sprites) {
+            if failed {
+                textures.get_or_insert_with(|| BatchTextures::from(&*fallback));
+                quads.push(fallback_quad(sprite, &fallback, transform));
+            } else if let Some(entry) = entries.get(&sprite.entry) {
+                textures.get_or_insert_with(|| BatchTextures::from(entry));
+                quads.extend(sprite_quads(sprite, entry, transform));
+            }
+        }
+        // 2D batches are placed at their z, so they sort against other 2D meshes
+        let offset = Vec3::Z * key.z.0;
+        let mesh = batch_mesh(quads.into_iter().map(|mut quad| {
+            quad.corners = quad.corners.map(|corner| corner - offset);
+            quad
+        }));
+
+        if batch.entity != Entity::PLACEHOLDER {
+            meshes.insert(&batch.mesh, mesh).unwrap();
+            continue;
+        }
+        let Some(textures) = textures else {
+            continue;
+        };
+        batch.mesh = meshes.add(mesh);
+        let mut entity = commands.spawn((
+            AtlasBatch {
+                image: key.image,
+                in_3d: key.in_3d,
+            },
+            // vertices are already in world space, apart from the z of the batch
+            Transform::from_translation(offset),
+            NoFrustumCulling,
+        ));
+        if key.in_3d {
+            entity.insert((
+                Mesh3d(batch.mesh.clone()),
+                MeshMaterial3d(materials_3d.add(AtlasMaterial {
+                    image: textures.image,
+                    palettes: textures.palettes,
+                    normal_map: textures.normal_map,
+                    emissive_map: textures.emissive_map,
+                    light: light.clone(),
+                })),
+            ));
+        } else {
+            entity.insert((
+                Mesh2d(batch.mesh.clone()),
+                MeshMaterial2d(materials_2d.add(AtlasMaterial2d {
+                    image: textures.image,
+                    palettes: textures.palettes,
+                    normal_map: textures.normal_map,
+                    emissive_map: textures.emissive_map,
+                    light: light.clone(),
+                })),
+            ));
+        }
+        batch.entity = entity.id();
+    }
+}
+
+/// Batch the sprite is drawn with, `None` while it is hidden or its entry isn't loaded
+fn batch_key(
+    sprite: &AtlasSprite,
+    transform: &GlobalTransform,
+    visibility: &InheritedVisibility,
+    in_3d: bool,
+    failed: bool,
+    entries: &Assets<AtlasEntry>,
+    fallback: &AtlasFallback,
+) -> Option<BatchKey> {
+    if !visibility.get() {
+        return None;
+    }
+    let image = match failed {
+        true => fallback.handle.id(),
+        false => entries.get(&sprite.entry)?.image.id(),
+    };
+    let z = match in_3d {
+        true => 0.0,
+        false => transform.translation().z,
+    };
+    Some(BatchKey {
+        image,
+        in_3d,
+        z: FloatOrd(z),
+    })
+}
+
+/// One quad per tile, entries larger than a tile are laid out around the entity's center
+fn sprite_quads<'a>(
+    sprite: &'a AtlasSprite,
+    entry: &'a AtlasEntry,
+    transform: &'a GlobalTransform,
+) -> impl Iterator<Item = Quad> + 'a {
+    let tiles = entry.definition.drawn_size(&sprite.state).as_vec2();
+    let size = sprite
+        .custom_size
+        .unwrap_or(entry.tile_size.as_vec2() * tiles);
+    let half = size / tiles / 2.0;
+
+    // layers are grown by the extrusion, only their inner part is the tile
+    let inset = entry.extrude as f32 / (entry.tile_size.as_vec2() + 2.0 * entry.extrude as f32);
+    let (left, right) = if sprite.flip_x {
+        (1.0 - inset.x, inset.x)
+    } else {
+        (inset.x, 1.0 - inset.x)
+    };
+    let (top, bottom) = if sprite.flip_y {
+        (1.0 - inset.y, inset.y)
+    } else {
+        (inset.y, 1.0 - inset.y)
+    };
+    // flipping mirrors the tile positions as well
+    let mirror = Vec2::new(
+        if sprite.flip_x { -1.0 } else { 1.0 },
+        if sprite.flip_y { -1.0 } else { 1.0 },
+    );
+    let color = sprite.color.to_linear().to_f32_array();
+    let palette = entry.palette_row(sprite.palette.as_deref());
+    let flip = sprite.flip_x as u32 | (sprite.flip_y as u32) << 1;
+
+    entry.layers(&sprite.state).map(move |(cell, layer)| {
+        // sheet rows grow downwards, world y upwards
+        let center = (cell.as_vec2() + 0.5 - tiles / 2.0) * Vec2::new(1.0, -1.0) * size / tiles;
+        let center = center * mirror;
+        let corners = [
+            Vec2::new(-half.x, -half.y),
+            Vec2::new(half.x, -half.y),
+            Vec2::new(half.x, half.y),
+            Vec2::new(-half.x, half.y),
+        ]
+        .map(|corner| transform.transform_point((center + corner).extend(0.0)));
+
+        Quad {
+            corners,
+            uvs: [[left, bottom], [right, bottom], [right, top], [left, top]],
+            color,
+            layer,
+            palette,
+            flip,
+        }
+    })
+}
+
+/// The whole fallback checkerboard, since the tile the entry would show is unknown
+fn fallback_quad(
+    sprite: &AtlasSprite,
+    fallback: &AtlasFallback,
+    transform: &GlobalTransform,
+) -> Quad {
+    let half = sprite.custom_size.unwrap_or(fallback.sprite_size) / 2.0;
+    let corners = [
+        Vec2::new(-half.x, -half.y),
+        Vec2::new(half.x, -half.y),
+        Vec2::new(half.x, half.y),
+        Vec2::new(-half.x, half.y),
+    ]
+    .map(|corner| transform.transform_point(corner.extend(0.0)));
+
+    Quad {
+        corners,
+        uvs: [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]],
+        color: sprite.color.to_linear().to_f32_array(),
+        layer: 0,
+        palette: 0,
+        flip: 0,
+    }
+}
+
+fn batch_mesh(quads: impl Iterator<Item = Quad>) -> Mesh {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut layers = Vec::new();
+    let mut palettes = Vec::new();
+    let mut flips = Vec::new();
+    let mut indices = Vec::new();
+
+    for quad in quads {
+        let first = positions.len() as u32;
+        indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+        positions.extend(quad.corners.map(|corner| corner.to_array()));
+        uvs.extend(quad.uvs);
+        colors.extend([quad.color; 4]);
+        layers.extend([quad.layer; 4]);
+        palettes.extend([quad.palette; 4]);
+        flips.extend([quad.flip; 4]);
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_COLOR, colors)
+    .with_inserted_attribute(ATTRIBUTE_ATLAS_LAYER, layers)
+    .with_inserted_attribute(ATTRIBUTE_ATLAS_PALETTE, palettes)
+    .with_inserted_attribute(ATTRIBUTE_ATLAS_FLIP, flips)
+    .with_inserted_indices(Indices::U32(indices))
+}
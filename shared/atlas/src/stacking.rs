@@ -0,0 +1,501 @@
+use bevy::asset::RenderAssetUsages;
+use bevy::image::ImageFilterMode;
+use bevy::image::ImageSampler;
+use bevy::image::ImageSamplerDescriptor;
+use bevy::prelude::*;
+use bevy::render::render_resource::Extent3d;
+use bevy::render::render_resource::TextureDataOrder;
+use bevy::render::render_resource::TextureDescriptor;
+use bevy::render::render_resource::TextureDimension;
+use bevy::render::render_resource::TextureFormat;
+use bevy::render::render_resource::TextureViewDescriptor;
+use bevy::render::render_resource::TextureViewDimension;
+use itertools::Itertools as _;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::AtlasError;
+use crate::palette_table;
+
+/// Cuts a sheet into `tile_size` tiles and stacks them row by row into an array texture.
+/// Returns the texture and the size of the sheet in tiles.
+///
+/// Block compressed sheets (BC, ETC2, ASTC) are cut along whole blocks, so `tile_size` has to be a multiple of the block size.
+/// Only the first mip level of the sheet is kept, lower levels would mix neighbouring tiles.
+pub fn tileset_to_stacked(image: &Image, tile_size: UVec2) -> Result<(Image, UVec2), AtlasError> {
+    let descriptor = &image.texture_descriptor;
+    if descriptor.dimension != TextureDimension::D2 {
+        return Err(AtlasError::UnsupportedDimension(descriptor.dimension));
+    }
+    if descriptor.size.depth_or_array_layers != 1 {
+        return Err(AtlasError::AlreadyLayered(
+            descriptor.size.depth_or_array_layers,
+        ));
+    }
+    if tile_size.min_element() == 0
+        || !image.width().is_multiple_of(tile_size.x)
+        || !image.height().is_multiple_of(tile_size.y)
+    {
+        return Err(AtlasError::TileSizeMismatch {
+            sheet: image.size(),
+            tile: tile_size,
+        });
+    }
+
+    let block = BlockLayout::of(descriptor.format)
+        .ok_or(AtlasError::UnsupportedFormat(descriptor.format))?;
+    if (tile_size % block.size).max_element() != 0 {
+        return Err(AtlasError::TileNotBlockAligned {
+            tile: tile_size,
+            block: block.size,
+        });
+    }
+
+    let tiles_w = descriptor.size.width / tile_size.x;
+    let tiles_h = descriptor.size.height / tile_size.y;
+
+    // everything below counts whole blocks, which are single pixels for uncompressed formats
+    let sheet_w = descriptor.size.width / block.size.x;
+    let tile = tile_size / block.size;
+
+    let data = image.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+
+    let mut data_new = Vec::with_capacity(data.len());
+
+    (0..tiles_h)
+        .cartesian_product(0..tiles_w)
+        .for_each(|(ty, tx)| {
+            let src_x0 = tx * tile.x;
+            let src_y0 = ty * tile.y;
+
+            (0..tile.y).for_each(|by| {
+                let src_block = (src_y0 + by) * sheet_w + src_x0;
+                let byte_offset = src_block as usize * block.bytes;
+                let byte_w = (tile.x as usize) * block.bytes;
+
+                data_new.extend_from_slice(&data[byte_offset..byte_offset + byte_w]);
+            });
+        });
+
+    Ok((
+        Image {
+            data: Some(data_new),
+            texture_descriptor: TextureDescriptor {
+                size: Extent3d {
+                    width: tile_size.x,
+                    height: tile_size.y,
+                    depth_or_array_layers: tiles_w * tiles_h,
+                },
+                mip_level_count: 1,
+                ..image.texture_descriptor
+            },
+            texture_view_descriptor: Some(TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2Array),
+                ..default()
+            }),
+            // sheets from KTX2 files are labeled mip major, the tiles are written layer by layer
+            data_order: TextureDataOrder::LayerMajor,
+            ..image.clone()
+        },
+        UVec2::new(tiles_w, tiles_h),
+    ))
+}
+
+/// How the stacked texture is prepared for filtering, shared by all atlas sources
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StackingOptions {
+    /// Pixels each layer is grown by on every side, repeating its border.
+    /// Keeps the edges of tiles crisp with linear filtering and at lower mip levels.
+    #[serde(default)]
+    pub extrude: u32,
+    /// Generates the full mip chain of every layer on the CPU
+    #[serde(default)]
+    pub mipmaps: bool,
+    /// Defaults to the sampler of the sheet, which follows the `ImagePlugin` default
+    #[serde(default)]
+    pub sampling: Option<Sampling>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sampling {
+    /// Pixel art
+    Nearest,
+    Linear,
+}
+
+impl Sampling {
+    pub fn sampler(self) -> ImageSampler {
+        let filter = match self {
+            Sampling::Nearest => ImageFilterMode::Nearest,
+            Sampling::Linear => ImageFilterMode::Linear,
+        };
+        ImageSampler::Descriptor(ImageSamplerDescriptor {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            ..ImageSamplerDescriptor::default()
+        })
+    }
+}
+
+/// [`tileset_to_stacked`] followed by the steps requested in `options`
+pub fn stack_sheet(
+    sheet: &Image,
+    tile_size: UVec2,
+    options: &StackingOptions,
+) -> Result<(Image, UVec2), AtlasError> {
+    let (mut stacked, grid) = tileset_to_stacked(sheet, tile_size)?;
+    if options.extrude > 0 {
+        stacked = extrude_layers(&stacked, options.extrude)?;
+    }
+    if options.mipmaps {
+        stacked = generate_mipmaps(&stacked)?;
+    }
+    if let Some(sampling) = options.sampling {
+        stacked.sampler = sampling.sampler();
+    }
+    Ok((stacked, grid))
+}
+
+/// Takes an array texture written by `bake_atlas` as it is, after checking it matches the definition.
+/// Of the `options` only `sampling` is applied, the rest was done while baking.
+pub fn check_baked(
+    mut stacked: Image,
+    tile_size: UVec2,
+    grid: UVec2,
+    options: &StackingOptions,
+) -> Result<Image, AtlasError> {
+    let size = stacked.texture_descriptor.size;
+    let size = UVec3::new(size.width, size.height, size.depth_or_array_layers);
+    let expected = (tile_size + 2 * options.extrude).extend(grid.element_product());
+    if size != expected {
+        return Err(AtlasError::BakedSize { size, expected });
+    }
+    // single layers would be loaded as plain 2D textures
+    stacked.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    if let Some(sampling) = options.sampling {
+        stacked.sampler = sampling.sampler();
+    }
+    Ok(stacked)
+}
+
+/// Grows every layer by `extrude` pixels on each side, copying the nearest border pixel
+pub fn extrude_layers(stacked: &Image, extrude: u32) -> Result<Image, AtlasError> {
+    let descriptor = &stacked.texture_descriptor;
+    // compressed blocks can't be repeated pixel by pixel
+    let bpp = BlockLayout::of(descriptor.format)
+        .filter(|block| block.size == UVec2::ONE)
+        .ok_or(AtlasError::UnsupportedFormat(descriptor.format))?
+        .bytes;
+    let data = stacked.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+
+    let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+    let grown = size + 2 * extrude;
+    let layer_bytes = size.element_product() as usize * bpp;
+
+    let mut data_new = Vec::with_capacity(
+        grown.element_product() as usize * bpp * descriptor.size.depth_or_array_layers as usize,
+    );
+    for layer in data.chunks_exact(layer_bytes) {
+        for y in 0..grown.y {
+            let src_y = y.saturating_sub(extrude).min(size.y - 1);
+            for x in 0..grown.x {
+                let src_x = x.saturating_sub(extrude).min(size.x - 1);
+                let src = (src_y * size.x + src_x) as usize * bpp;
+                data_new.extend_from_slice(&layer[src..src + bpp]);
+            }
+        }
+    }
+
+    Ok(Image {
+        data: Some(data_new),
+        texture_descriptor: TextureDescriptor {
+            size: Extent3d {
+                width: grown.x,
+                height: grown.y,
+                ..descriptor.size
+            },
+            ..stacked.texture_descriptor.clone()
+        },
+        data_order: TextureDataOrder::LayerMajor,
+        ..stacked.clone()
+    })
+}
+
+/// Appends the mip chain of every layer, averaging 2x2 blocks weighted by alpha.
+/// Only 8 bit RGBA / BGRA formats are supported, sRGB ones are averaged in linear space.
+pub fn generate_mipmaps(stacked: &Image) -> Result<Image, AtlasError> {
+    let descriptor = &stacked.texture_descriptor;
+    let srgb = match descriptor.format {
+        TextureFormat::Rgba8UnormSrgb | TextureFormat::Bgra8UnormSrgb => true,
+        TextureFormat::Rgba8Unorm | TextureFormat::Bgra8Unorm => false,
+        format => return Err(AtlasError::UnsupportedFormat(format)),
+    };
+    let data = stacked.data.as_ref().ok_or(AtlasError::NoCpuData)?;
+
+    let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+    let levels = 32 - size.max_element().leading_zeros();
+    let layer_bytes = size.element_product() as usize * 4;
+
+    // layer major: every layer is followed by its own smaller levels
+    let mut data_new = Vec::with_capacity(data.len() * 2);
+    for layer in data.chunks_exact(layer_bytes) {
+        data_new.extend_from_slice(layer);
+        let mut level = layer.to_vec();
+        let mut level_size = size;
+        for _ in 1..levels {
+            (level, level_size) = downsample(&level, level_size, srgb);
+            data_new.extend_from_slice(&level);
+        }
+    }
+
+    Ok(Image {
+        data: Some(data_new),
+        texture_descriptor: TextureDescriptor {
+            mip_level_count: levels,
+            ..stacked.texture_descriptor.clone()
+        },
+        data_order: TextureDataOrder::LayerMajor,
+        ..stacked.clone()
+    })
+}
+
+fn downsample(level: &[u8], size: UVec2, srgb: bool) -> (Vec<u8>, UVec2) {
+    let to_linear = |c: u8| match srgb {
+        true => (c as f32 / 255.0).powf(2.2),
+        false => c as f32 / 255.0,
+    };
+    let from_linear = |c: f32| match srgb {
+        true => (c.powf(1.0 / 2.2) * 255.0).round() as u8,
+        false => (c * 255.0).round() as u8,
+    };
+
+    let next = (size / 2).max(UVec2::ONE);
+    let mut out = Vec::with_capacity(next.element_product() as usize * 4);
+    for y in 0..next.y {
+        for x in 0..next.x {
+            let mut color = [0.0; 3];
+            let mut alpha = 0.0;
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let sx = (x * 2 + dx).min(size.x - 1);
+                let sy = (y * 2 + dy).min(size.y - 1);
+                let px = &level[(sy * size.x + sx) as usize * 4..][..4];
+                let a = px[3] as f32 / 255.0;
+                for c in 0..3 {
+                    color[c] += to_linear(px[c]) * a;
+                }
+                alpha += a;
+            }
+            for c in color {
+                out.push(match alpha > 0.0 {
+                    true => from_linear(c / alpha),
+                    false => 0,
+                });
+            }
+            out.push((alpha / 4.0 * 255.0).round() as u8);
+        }
+    }
+    (out, next)
+}
+
+/// Copies the first mip level of `layer` into a 2D image of its own.
+/// `None` for block compressed formats, missing layers or without CPU data.
+pub fn stacked_layer(stacked: &Image, layer: u32) -> Option<Image> {
+    let descriptor = &stacked.texture_descriptor;
+    let bpp = BlockLayout::of(descriptor.format)
+        .filter(|block| block.size == UVec2::ONE)?
+        .bytes;
+    if layer >= descriptor.size.depth_or_array_layers {
+        return None;
+    }
+
+    let size = UVec2::new(descriptor.size.width, descriptor.size.height);
+    let start = match stacked.data_order {
+        // every layer is followed by its own mip chain
+        TextureDataOrder::LayerMajor => {
+            let layer_bytes = (0..descriptor.mip_level_count)
+                .map(|mip| (size >> mip).max(UVec2::ONE).element_product() as usize * bpp)
+                .sum::<usize>();
+            layer as usize * layer_bytes
+        }
+        // baked atlases from KTX2 files start with the first level of all layers
+        TextureDataOrder::MipMajor => layer as usize * size.element_product() as usize * bpp,
+    };
+    let data = stacked
+        .data
+        .as_ref()?
+        .get(start..start + size.element_product() as usize * bpp)?;
+
+    Some(Image {
+        sampler: stacked.sampler.clone(),
+        ..Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data.to_vec(),
+            descriptor.format,
+            RenderAssetUsages::default(),
+        )
+    })
+}
+
+/// Smallest unit of a format that can be copied on its own, a single pixel unless the format is block compressed
+#[derive(Debug, Clone, Copy)]
+struct BlockLayout {
+    size: UVec2,
+    bytes: usize,
+}
+
+impl BlockLayout {
+    /// `None` for formats whose aspects are stored separately, like combined depth stencil
+    fn of(format: TextureFormat) -> Option<Self> {
+        let (width, height) = format.block_dimensions();
+        Some(Self {
+            size: UVec2::new(width, height),
+            bytes: format.block_copy_size(None)? as usize,
+        })
+    }
+}
+
+/// Single layer checkerboard shown in place of atlases that failed to load,
+/// see [`AtlasLoadFailed`](crate::AtlasLoadFailed)
+#[derive(Debug, Clone, Resource)]
+pub struct AtlasFallback {
+    pub handle: Handle<Image>,
+    /// Empty palette table, the checkerboard is never recolored
+    pub palettes: Handle<Image>,
+    /// Size of the sprites without a `custom_size`, the size of their entry is unknown
+    pub sprite_size: Vec2,
+}
+
+impl FromWorld for AtlasFallback {
+    fn from_world(world: &mut World) -> Self {
+        const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+        const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+        let mut image = Image::new(
+            Extent3d {
+                width: 2,
+                height: 2,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            [MAGENTA, BLACK, BLACK, MAGENTA].concat(),
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.texture_view_descriptor = Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..default()
+        });
+        image.sampler = ImageSampler::nearest();
+
+        let mut images = world.resource_mut::<Assets<Image>>();
+        Self {
+            handle: images.add(image),
+            palettes: images.add(palette_table(&default()).0),
+            sprite_size: Vec2::splat(16.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sheet of `size` whose blocks are filled with their index
+    fn sheet(size: UVec2, format: TextureFormat) -> Image {
+        let block = BlockLayout::of(format).unwrap();
+        let blocks = (size / block.size).element_product() as usize;
+        let data = (0..blocks)
+            .flat_map(|index| vec![index as u8; block.bytes])
+            .collect();
+        Image::new(
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            format,
+            RenderAssetUsages::default(),
+        )
+    }
+
+    /// Block indices in the order they were stacked
+    fn stacked_blocks(stacked: &Image, format: TextureFormat) -> Vec<u8> {
+        let bytes = BlockLayout::of(format).unwrap().bytes;
+        let data = stacked.data.as_ref().unwrap();
+        assert!(
+            data.chunks_exact(bytes)
+                .all(|b| b.iter().all(|x| *x == b[0]))
+        );
+        data.chunks_exact(bytes).map(|block| block[0]).collect()
+    }
+
+    #[test]
+    fn compressed_tiles_are_cut_along_blocks() {
+        // 4 x 2 blocks of 4 x 4 pixels, cut into tiles of 2 x 1 blocks
+        let format = TextureFormat::Bc1RgbaUnormSrgb;
+        let (stacked, grid) =
+            tileset_to_stacked(&sheet(UVec2::new(16, 8), format), UVec2::new(8, 4)).unwrap();
+        assert_eq!(grid, UVec2::new(2, 2));
+        assert_eq!(stacked.texture_descriptor.size.depth_or_array_layers, 4);
+        assert_eq!(stacked.texture_descriptor.format, format);
+        assert_eq!(stacked_blocks(&stacked, format), [0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // two rows of blocks per tile take one block from each row
+        let format = TextureFormat::Bc7RgbaUnorm;
+        let (stacked, grid) =
+            tileset_to_stacked(&sheet(UVec2::new(8, 8), format), UVec2::new(4, 8)).unwrap();
+        assert_eq!(grid, UVec2::new(2, 1));
+        assert_eq!(stacked_blocks(&stacked, format), [0, 2, 1, 3]);
+    }
+
+    #[test]
+    fn wide_formats_keep_whole_pixels() {
+        let format = TextureFormat::Rgba32Float;
+        let (stacked, grid) =
+            tileset_to_stacked(&sheet(UVec2::new(4, 2), format), UVec2::new(2, 1)).unwrap();
+        assert_eq!(grid, UVec2::new(2, 2));
+        assert_eq!(stacked_blocks(&stacked, format), [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn tiles_must_cover_whole_blocks() {
+        let sheet = sheet(UVec2::new(8, 8), TextureFormat::Bc1RgbaUnorm);
+        match tileset_to_stacked(&sheet, UVec2::new(2, 4)) {
+            Err(AtlasError::TileNotBlockAligned { tile, block }) => {
+                assert_eq!((tile, block), (UVec2::new(2, 4), UVec2::splat(4)));
+            }
+            other => panic!("expected unaligned tiles, got {other:?}"),
+        }
+        assert!(matches!(
+            tileset_to_stacked(&sheet, UVec2::new(8, 3)),
+            Err(AtlasError::TileSizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn compressed_layers_are_not_copied_out() {
+        let format = TextureFormat::Bc1RgbaUnorm;
+        let (stacked, _) =
+            tileset_to_stacked(&sheet(UVec2::new(8, 4), format), UVec2::splat(4)).unwrap();
+        assert!(stacked_layer(&stacked, 0).is_none());
+
+        let format = TextureFormat::Rgba8Unorm;
+        let (stacked, _) =
+            tileset_to_stacked(&sheet(UVec2::new(2, 2), format), UVec2::ONE).unwrap();
+        let layer = stacked_layer(&stacked, 3).unwrap();
+        assert_eq!(layer.data.unwrap(), [3; 4]);
+        assert!(stacked_layer(&stacked, 4).is_none());
+    }
+}
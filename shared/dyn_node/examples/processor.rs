@@ -49,8 +49,9 @@ fn get_enemies(
                 let h_dyn = handle.clone();
                 async move {
                     asset_server.wait_for_asset(&h_dyn).await.unwrap();
+                    // all three lookups are answered by a single run of `query_nodes_sync`
                     async_service
-                        .exec_sync(query_node_sync, (h_dyn, path.to_string()))
+                        .exec_sync_batched(query_nodes_sync, (h_dyn, path.to_string()))
                         .await
                 }
             })
@@ -58,11 +59,14 @@ fn get_enemies(
         .collect()
 }
 
-fn query_node_sync(
-    In((h, path)): In<(Handle<DynNode>, String)>,
+fn query_nodes_sync(
+    In(requests): In<Vec<(Handle<DynNode>, String)>>,
     nodes: Res<Assets<DynNode>>,
-) -> Result<Enemy, QueryError> {
-    nodes.get(&h).unwrap().query::<Enemy>(&path)
+) -> Vec<Result<Enemy, QueryError>> {
+    requests
+        .iter()
+        .map(|(h, path)| nodes.get(h).unwrap().query::<Enemy>(path))
+        .collect()
 }
 
 fn print_enemies(enemies: Res<Enemies>, assets: Res<Assets<Enemy>>) {